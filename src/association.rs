@@ -0,0 +1,58 @@
+use crate::{
+    config::{Association, AssociationMode, AssociationRule},
+    packets::TAState,
+    proto::models,
+};
+
+/// The guids of the relay users to add to a new match, leaving out those
+/// already in it and `own_tx`, this relay's TX user.
+pub fn relay_users(
+    association: &Association,
+    state: &TAState,
+    r#match: &models::Match,
+    own_tx: Option<&str>,
+) -> Vec<String> {
+    let candidates = state
+        .server_users
+        .iter()
+        .filter(|u| Some(u.guid.as_str()) != own_tx)
+        .filter(|u| !r#match.associated_users.contains(&u.guid));
+    match association.mode {
+        AssociationMode::None => vec![],
+        AssociationMode::All => candidates.map(|u| u.guid.clone()).collect(),
+        AssociationMode::Rules => candidates
+            .filter(|u| {
+                association
+                    .rules
+                    .iter()
+                    .any(|rule| picks(rule, u) && applies_to(rule, state, r#match))
+            })
+            .map(|u| u.guid.clone())
+            .collect(),
+    }
+}
+
+fn picks(rule: &AssociationRule, user: &models::User) -> bool {
+    rule.users
+        .iter()
+        .any(|u| *u == user.guid || *u == user.name)
+}
+
+fn applies_to(rule: &AssociationRule, state: &TAState, r#match: &models::Match) -> bool {
+    rule.matches_with.is_empty()
+        || r#match
+            .associated_users
+            .iter()
+            .filter_map(|guid| {
+                state
+                    .players
+                    .iter()
+                    .chain(&state.coordinators)
+                    .find(|u| u.guid == *guid)
+            })
+            .any(|u| {
+                rule.matches_with
+                    .iter()
+                    .any(|w| *w == u.guid || *w == u.name || *w == u.user_id)
+            })
+}
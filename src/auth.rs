@@ -0,0 +1,268 @@
+use std::{net::IpAddr, path::Path, sync::Arc};
+
+use async_graphql::{Context, ErrorExtensions, Guard};
+use poem::{http::StatusCode, Endpoint, IntoResponse, Request, Response};
+use serde::Deserialize;
+
+/// What a client is allowed to do. Each role can do everything the roles before it can.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Read-only access, for overlays.
+    Overlay,
+    /// Can run the show: pick/bans, bracket results and the like.
+    Producer,
+    /// Can change the structure of the event.
+    Admin,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKey {
+    pub name: String,
+    pub key: String,
+    pub role: Role,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyFile {
+    #[serde(default)]
+    keys: Vec<ApiKey>,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+const PUBLIC_PATHS: [&str; 2] = ["/healthz", "/readyz"];
+
+/// The graphql-ws endpoint, whose clients can authenticate in `connection_init`.
+const GRAPHQL_WS_PATH: &str = "/graphql/ws";
+
+/// The streaming endpoints browser sources connect to, which can't set headers and
+/// so may pass their key as `?token=`. Everywhere else it has to be a header.
+const QUERY_TOKEN_PATHS: [&str; 4] = ["/ws/json", "/events", "/ws/protobuf", GRAPHQL_WS_PATH];
+
+/// Who made a request, for rate limiting: the API key's name, or the remote address
+/// if no key was used.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClientId(pub String);
+
+/// The configured API keys. Without any, auth is disabled and everyone is an admin.
+#[derive(Clone, Default)]
+pub struct Keys(Option<Arc<Vec<ApiKey>>>);
+
+impl Keys {
+    pub fn disabled() -> Self {
+        Self(None)
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read keys file {}: {}", path.display(), e))?;
+        let file: KeyFile = toml::from_str(&data)
+            .map_err(|e| anyhow::anyhow!("Failed to parse keys file {}: {}", path.display(), e))?;
+
+        if let Some(key) = file.keys.iter().find(|k| k.key.len() < 16) {
+            return Err(anyhow::anyhow!(
+                "Key {} is too short, keys need at least 16 characters.",
+                key.name
+            ));
+        }
+        for (i, key) in file.keys.iter().enumerate() {
+            if file.keys[..i].iter().any(|k| k.key == key.key) {
+                return Err(anyhow::anyhow!("Key {} is used twice.", key.name));
+            }
+        }
+
+        Ok(Self(Some(Arc::new(file.keys))))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    fn find(&self, token: Option<&str>) -> Option<&ApiKey> {
+        let token = token?;
+        self.0
+            .as_ref()?
+            .iter()
+            .find(|k| constant_time_eq(k.key.as_bytes(), token.as_bytes()))
+    }
+
+    /// The role a token grants, if any.
+    pub fn authenticate(&self, token: Option<&str>) -> Option<Role> {
+        match self.is_enabled() {
+            true => self.find(token).map(|k| k.role),
+            false => Some(Role::Admin),
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn strip_bearer(value: &str) -> &str {
+    value.strip_prefix("Bearer ").unwrap_or(value).trim()
+}
+
+/// Finds the token in the `Authorization` or `X-Api-Key` headers, or on the streaming
+/// endpoints in the `token` query parameter, for clients that can't set headers (like
+/// OBS browser sources).
+fn request_token(req: &Request) -> Option<String> {
+    req.header("authorization")
+        .map(strip_bearer)
+        .or(req.header("x-api-key"))
+        .map(|t| t.to_string())
+        .or_else(|| {
+            QUERY_TOKEN_PATHS
+                .contains(&req.uri().path())
+                .then(|| req.params::<TokenQuery>().ok().and_then(|q| q.token))
+                .flatten()
+        })
+}
+
+/// The address a request came from. Behind a trusted proxy, that's the last
+/// address in `X-Forwarded-For` the proxies didn't add themselves.
+fn client_ip(req: &Request, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let remote = req.remote_addr().as_socket_addr()?.ip();
+    if !trusted_proxies.contains(&remote) {
+        return Some(remote);
+    }
+
+    let forwarded = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|a| a.trim().parse::<IpAddr>().ok())
+        .collect::<Vec<_>>();
+    Some(
+        forwarded
+            .into_iter()
+            .rev()
+            .find(|ip| !trusted_proxies.contains(ip))
+            .unwrap_or(remote),
+    )
+}
+
+fn is_websocket_upgrade(req: &Request) -> bool {
+    let connection_upgrade = req.header("connection").is_some_and(|v| {
+        v.split(',')
+            .any(|t| t.trim().eq_ignore_ascii_case("upgrade"))
+    });
+    let upgrade_websocket = req
+        .header("upgrade")
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("websocket"));
+    connection_upgrade && upgrade_websocket
+}
+
+/// Authenticates every request, storing the client's [`Role`] and [`ClientId`] in the
+/// request extensions.
+///
+/// graphql-ws upgrades without a token are let through without a role, as those
+/// clients usually send theirs in the `connection_init` payload instead.
+pub async fn middleware<E: Endpoint>(
+    ep: Arc<E>,
+    mut req: Request,
+    keys: Keys,
+    trusted_proxies: Arc<[IpAddr]>,
+) -> poem::Result<Response> {
+    // health checks come from orchestrators that don't have a key
+    if PUBLIC_PATHS.contains(&req.uri().path()) {
+        return ep.call(req).await.map(IntoResponse::into_response);
+    }
+
+    let token = request_token(&req);
+    let client = match keys.find(token.as_deref()) {
+        Some(key) => key.name.clone(),
+        None => client_ip(&req, &trusted_proxies)
+            .map(|ip| ip.to_string())
+            .unwrap_or_default(),
+    };
+    req.extensions_mut().insert(ClientId(client));
+
+    match keys.authenticate(token.as_deref()) {
+        Some(role) => {
+            req.extensions_mut().insert(role);
+        }
+        None if token.is_none()
+            && req.uri().path() == GRAPHQL_WS_PATH
+            && is_websocket_upgrade(&req) => {}
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body("Missing or invalid API key"))
+        }
+    }
+    ep.call(req).await.map(IntoResponse::into_response)
+}
+
+/// Authenticates a graphql-ws connection from its `connection_init` payload,
+/// unless the upgrade request already did.
+///
+/// The connection's data also gets its [`ClientId`], the key's name if the payload
+/// had one, so each operation on it can be rate limited.
+pub fn connection_init(
+    keys: &Keys,
+    role: Option<Role>,
+    client: ClientId,
+    payload: &serde_json::Value,
+) -> async_graphql::Result<async_graphql::Data> {
+    let (role, client) = match role {
+        Some(role) => (Some(role), client),
+        None => {
+            let token = ["token", "Authorization", "authorization"]
+                .iter()
+                .find_map(|k| payload.get(k).and_then(|v| v.as_str()))
+                .map(strip_bearer);
+            let client = match keys.find(token) {
+                Some(key) => ClientId(key.name.clone()),
+                None => client,
+            };
+            (keys.authenticate(token), client)
+        }
+    };
+
+    match role {
+        Some(role) => {
+            let mut data = async_graphql::Data::default();
+            data.insert(role);
+            data.insert(client);
+            Ok(data)
+        }
+        None => Err(unauthorized()),
+    }
+}
+
+fn unauthorized() -> async_graphql::Error {
+    async_graphql::Error::new("Missing or invalid API key")
+        .extend_with(|_, e| e.set("code", "UNAUTHENTICATED"))
+}
+
+/// Only lets clients with at least the given role through.
+pub struct RoleGuard(Role);
+
+impl RoleGuard {
+    pub fn new(role: Role) -> Self {
+        Self(role)
+    }
+}
+
+impl Guard for RoleGuard {
+    async fn check(&self, ctx: &Context<'_>) -> async_graphql::Result<()> {
+        match ctx.data_opt::<Role>() {
+            Some(role) if *role >= self.0 => Ok(()),
+            Some(_) => Err(async_graphql::Error::new(format!(
+                "This requires the {:?} role",
+                self.0
+            ))
+            .extend_with(|_, e| e.set("code", "FORBIDDEN"))),
+            None => Err(unauthorized()),
+        }
+    }
+}
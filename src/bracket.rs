@@ -0,0 +1,687 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use async_graphql::{Enum, InputObject, SimpleObject};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    packets::TAState,
+    proto::packet::{
+        self,
+        push::{self, song_finished::CompletionType},
+    },
+};
+
+const BRACKET_FILE: &str = "bracket.json";
+
+#[derive(Enum, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub enum BracketKind {
+    SingleElimination,
+    DoubleElimination,
+}
+
+#[derive(Enum, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub enum BracketSide {
+    Winners,
+    Losers,
+    /// The grand final, and its reset if the losers bracket's finalist wins it.
+    GrandFinal,
+}
+
+#[derive(SimpleObject, Serialize, Deserialize, Clone)]
+pub struct Seed {
+    pub seed: i32,
+    pub name: String,
+    pub user_id: String,
+}
+
+#[derive(InputObject, Clone)]
+pub struct InputSeed {
+    pub seed: i32,
+    pub name: String,
+    pub user_id: String,
+}
+
+#[derive(SimpleObject, Serialize, Deserialize, Clone)]
+pub struct Tournament {
+    pub name: String,
+    pub kind: BracketKind,
+    pub best_of: i32,
+    pub seeds: Vec<Seed>,
+    pub rounds: Vec<Round>,
+}
+
+#[derive(SimpleObject, Serialize, Deserialize, Clone)]
+pub struct Round {
+    pub side: BracketSide,
+    pub index: i32,
+    pub name: String,
+    pub slots: Vec<BracketSlot>,
+}
+
+#[derive(SimpleObject, Serialize, Deserialize, Clone)]
+pub struct BracketSlot {
+    pub guid: Uuid,
+    pub position: i32,
+    pub top: Option<Seed>,
+    pub bottom: Option<Seed>,
+    pub top_wins: i32,
+    pub bottom_wins: i32,
+    pub winner: Option<Seed>,
+    /// The TA match this slot is being played in.
+    pub match_guid: Option<Uuid>,
+    pub winner_to: Option<Uuid>,
+    pub loser_to: Option<Uuid>,
+
+    #[graphql(skip)]
+    pub winner_to_top: bool,
+    #[graphql(skip)]
+    pub loser_to_top: bool,
+    /// Whether an entrant is still expected from an earlier slot.
+    #[graphql(skip)]
+    pub top_pending: bool,
+    #[graphql(skip)]
+    pub bottom_pending: bool,
+    /// Scores of the map currently being played, keyed by user id.
+    #[graphql(skip)]
+    pub map_scores: HashMap<String, i32>,
+    /// Who failed or quit the map currently being played, by user id.
+    #[graphql(skip)]
+    #[serde(default)]
+    pub map_failed: HashSet<String>,
+}
+
+/// What the bracket needs to know about a packet, read from the state it was
+/// routed into, so the bracket can be updated once the state is unlocked.
+pub enum Observation {
+    /// A match was created or updated, with these players in it.
+    Match { guid: Uuid, user_ids: Vec<String> },
+    /// A player finished a map in these matches. A map failed or quit loses to
+    /// any map passed, whatever the scores.
+    SongFinished {
+        user_id: String,
+        score: i32,
+        completion: CompletionType,
+        matches: Vec<Uuid>,
+    },
+}
+
+#[derive(Default)]
+pub struct BracketState {
+    pub tournament: Option<Tournament>,
+}
+
+impl InputSeed {
+    pub fn into_seed(self) -> Seed {
+        Seed {
+            seed: self.seed,
+            name: self.name,
+            user_id: self.user_id,
+        }
+    }
+}
+
+impl BracketSlot {
+    fn new(position: usize) -> Self {
+        Self {
+            guid: Uuid::new_v4(),
+            position: position as i32,
+            top: None,
+            bottom: None,
+            top_wins: 0,
+            bottom_wins: 0,
+            winner: None,
+            match_guid: None,
+            winner_to: None,
+            loser_to: None,
+            winner_to_top: false,
+            loser_to_top: false,
+            top_pending: true,
+            bottom_pending: true,
+            map_scores: HashMap::new(),
+            map_failed: HashSet::new(),
+        }
+    }
+
+    fn has_entrant(&self, user_id: &str) -> bool {
+        [&self.top, &self.bottom]
+            .into_iter()
+            .flatten()
+            .any(|s| s.user_id == user_id)
+    }
+}
+
+/// Resolves a seed file relative to the data directory, refusing any file outside
+/// it: absolute paths, `..` and symlinks out of it.
+pub async fn seed_path(config: &Config, file: &str) -> anyhow::Result<PathBuf> {
+    let outside = || anyhow::anyhow!("Seed file must be inside the data directory.");
+    if Path::new(file).is_absolute() {
+        return Err(outside());
+    }
+    let data_dir = tokio::fs::canonicalize(&config.data_dir).await?;
+    let path = tokio::fs::canonicalize(data_dir.join(file))
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to read seed file {}: {}", file, e))?;
+    if !path.starts_with(&data_dir) {
+        return Err(outside());
+    }
+    Ok(path)
+}
+
+/// Reads seeds from a `.csv` (with a `seed,name,user_id` header) or `.json` file.
+pub async fn read_seeds(path: &str) -> anyhow::Result<Vec<Seed>> {
+    let data = tokio::fs::read(path).await?;
+    let mut seeds = if path.ends_with(".csv") {
+        csv::Reader::from_reader(data.as_slice())
+            .deserialize()
+            .collect::<Result<Vec<Seed>, _>>()?
+    } else if path.ends_with(".json") {
+        serde_json::from_slice::<Vec<Seed>>(&data)?
+    } else {
+        return Err(anyhow::anyhow!(
+            "Seed file {} must be a .csv or .json file.",
+            path
+        ));
+    };
+    seeds.sort_by_key(|s| s.seed);
+    Ok(seeds)
+}
+
+/// The order seeds are placed into the first round, so that the top seeds meet last.
+fn seed_order(size: usize) -> Vec<usize> {
+    let mut order = vec![1];
+    while order.len() < size {
+        let len = order.len() * 2;
+        order = order.iter().flat_map(|s| [*s, len + 1 - s]).collect();
+    }
+    order
+}
+
+fn round_name(side: BracketSide, slots: usize, index: usize, last: bool) -> String {
+    match (side, slots, last) {
+        (BracketSide::GrandFinal, _, true) => "Grand Final Reset".to_string(),
+        (BracketSide::GrandFinal, _, false) => "Grand Final".to_string(),
+        (BracketSide::Losers, _, true) => "Losers Final".to_string(),
+        (BracketSide::Losers, _, false) => format!("Losers Round {}", index + 1),
+        (BracketSide::Winners, 1, _) => "Final".to_string(),
+        (BracketSide::Winners, 2, _) => "Semifinals".to_string(),
+        (BracketSide::Winners, 4, _) => "Quarterfinals".to_string(),
+        (BracketSide::Winners, n, _) => format!("Round of {}", n * 2),
+    }
+}
+
+fn link(from: &mut BracketSlot, to: &BracketSlot, top: bool, loser: bool) {
+    if loser {
+        from.loser_to = Some(to.guid);
+        from.loser_to_top = top;
+    } else {
+        from.winner_to = Some(to.guid);
+        from.winner_to_top = top;
+    }
+}
+
+impl Tournament {
+    pub fn new(
+        name: String,
+        kind: BracketKind,
+        best_of: i32,
+        mut seeds: Vec<Seed>,
+    ) -> anyhow::Result<Self> {
+        if seeds.len() < 2 {
+            return Err(anyhow::anyhow!("A bracket needs at least two seeds."));
+        }
+        if kind == BracketKind::DoubleElimination && seeds.len() < 4 {
+            return Err(anyhow::anyhow!(
+                "A double elimination bracket needs at least four seeds."
+            ));
+        }
+        if best_of < 1 {
+            return Err(anyhow::anyhow!("best_of must be at least 1."));
+        }
+        seeds.sort_by_key(|s| s.seed);
+
+        let size = seeds.len().next_power_of_two();
+        let order = seed_order(size);
+
+        // winners bracket
+        let mut winners: Vec<Vec<BracketSlot>> = vec![];
+        let mut count = size / 2;
+        while count >= 1 {
+            winners.push((0..count).map(BracketSlot::new).collect());
+            count /= 2;
+        }
+        for (i, slot) in winners[0].iter_mut().enumerate() {
+            slot.top = seeds.get(order[i * 2] - 1).cloned();
+            slot.bottom = seeds.get(order[i * 2 + 1] - 1).cloned();
+            slot.top_pending = false;
+            slot.bottom_pending = false;
+        }
+        for r in 0..winners.len() - 1 {
+            let (current, next) = winners.split_at_mut(r + 1);
+            for (i, slot) in current[r].iter_mut().enumerate() {
+                link(slot, &next[0][i / 2], i % 2 == 0, false);
+            }
+        }
+
+        let mut losers: Vec<Vec<BracketSlot>> = vec![];
+        let mut grand_final = vec![];
+        let mut reset = vec![];
+        if kind == BracketKind::DoubleElimination {
+            // the losers bracket alternates between rounds that take in losers
+            // from the winners bracket and rounds that halve the field
+            let rounds = 2 * (winners.len() - 1);
+            for r in 0..rounds {
+                let count = size / 2usize.pow((r / 2 + 2) as u32);
+                losers.push((0..count).map(BracketSlot::new).collect());
+            }
+
+            for (i, slot) in winners[0].iter_mut().enumerate() {
+                link(slot, &losers[0][i / 2], i % 2 == 0, true);
+            }
+            for r in 0..rounds {
+                let (current, next) = losers.split_at_mut(r + 1);
+                let current = &mut current[r];
+                if r % 2 == 1 {
+                    let dropping = &mut winners[r / 2 + 1];
+                    let len = dropping.len();
+                    for (i, slot) in dropping.iter_mut().enumerate() {
+                        // reversed, to avoid immediate rematches
+                        link(slot, &current[len - 1 - i], false, true);
+                    }
+                }
+                if let Some(next) = next.first() {
+                    for (i, slot) in current.iter_mut().enumerate() {
+                        match r % 2 {
+                            0 => link(slot, &next[i], true, false),
+                            _ => link(slot, &next[i / 2], i % 2 == 0, false),
+                        }
+                    }
+                }
+            }
+
+            grand_final.push(BracketSlot::new(0));
+            if let Some(slot) = winners.last_mut().and_then(|r| r.last_mut()) {
+                link(slot, &grand_final[0], true, false);
+            }
+            if let Some(slot) = losers.last_mut().and_then(|r| r.last_mut()) {
+                link(slot, &grand_final[0], false, false);
+            }
+
+            // played only if the losers bracket's finalist wins the grand final,
+            // see `finish`
+            reset.push(BracketSlot::new(0));
+            link(&mut grand_final[0], &reset[0], false, false);
+            link(&mut grand_final[0], &reset[0], true, true);
+        }
+
+        let mut rounds = vec![];
+        for (side, side_rounds) in [
+            (BracketSide::Winners, winners),
+            (BracketSide::Losers, losers),
+            (BracketSide::GrandFinal, vec![grand_final, reset]),
+        ] {
+            let len = side_rounds.len();
+            for (i, slots) in side_rounds.into_iter().enumerate() {
+                if slots.is_empty() {
+                    continue;
+                }
+                rounds.push(Round {
+                    side,
+                    index: i as i32,
+                    name: round_name(side, slots.len(), i, i + 1 == len),
+                    slots,
+                });
+            }
+        }
+
+        let mut tournament = Tournament {
+            name,
+            kind,
+            best_of,
+            seeds,
+            rounds,
+        };
+        // advance anyone who got a bye in the first round
+        let first = tournament.rounds[0]
+            .slots
+            .iter()
+            .map(|s| s.guid)
+            .collect::<Vec<_>>();
+        for guid in first {
+            tournament.resolve_bye(guid);
+        }
+        Ok(tournament)
+    }
+
+    fn slot(&self, guid: Uuid) -> Option<&BracketSlot> {
+        self.rounds
+            .iter()
+            .flat_map(|r| r.slots.iter())
+            .find(|s| s.guid == guid)
+    }
+
+    fn slot_mut(&mut self, guid: Uuid) -> Option<&mut BracketSlot> {
+        self.rounds
+            .iter_mut()
+            .flat_map(|r| r.slots.iter_mut())
+            .find(|s| s.guid == guid)
+    }
+
+    /// Whether a slot is the grand final, rather than its reset.
+    fn is_grand_final(&self, guid: Uuid) -> bool {
+        self.rounds
+            .iter()
+            .find(|r| r.side == BracketSide::GrandFinal && r.index == 0)
+            .is_some_and(|r| r.slots.iter().any(|s| s.guid == guid))
+    }
+
+    fn wins_needed(&self) -> i32 {
+        self.best_of / 2 + 1
+    }
+
+    /// Places an entrant (or nobody, if they had a bye) into a slot.
+    fn deliver(&mut self, guid: Uuid, top: bool, seed: Option<Seed>) {
+        if let Some(slot) = self.slot_mut(guid) {
+            match top {
+                true => {
+                    slot.top = seed;
+                    slot.top_pending = false;
+                }
+                false => {
+                    slot.bottom = seed;
+                    slot.bottom_pending = false;
+                }
+            }
+        }
+        self.resolve_bye(guid);
+    }
+
+    /// Advances a slot that can never be played because an entrant is missing.
+    fn resolve_bye(&mut self, guid: Uuid) {
+        let slot = match self.slot(guid) {
+            Some(slot) => slot,
+            None => return,
+        };
+        if slot.top_pending || slot.bottom_pending || slot.winner.is_some() {
+            return;
+        }
+        match (&slot.top, &slot.bottom) {
+            (Some(_), Some(_)) => {}
+            (Some(_), None) => self.finish(guid, true),
+            (None, Some(_)) => self.finish(guid, false),
+            (None, None) => {
+                let (winner_to, loser_to) = (
+                    slot.winner_to.map(|g| (g, slot.winner_to_top)),
+                    slot.loser_to.map(|g| (g, slot.loser_to_top)),
+                );
+                for (guid, top) in [winner_to, loser_to].into_iter().flatten() {
+                    self.deliver(guid, top, None);
+                }
+            }
+        }
+    }
+
+    /// Marks a slot as won and sends both entrants on to their next slots.
+    fn finish(&mut self, guid: Uuid, top_won: bool) {
+        let grand_final = self.is_grand_final(guid);
+        let slot = match self.slot_mut(guid) {
+            Some(slot) => slot,
+            None => return,
+        };
+        let (winner, mut loser) = match top_won {
+            true => (slot.top.clone(), slot.bottom.clone()),
+            false => (slot.bottom.clone(), slot.top.clone()),
+        };
+        if let Some(winner) = &winner {
+            info!("{} won bracket slot {}", winner.name, guid);
+        }
+        slot.winner = winner.clone();
+        let (mut winner_to, mut loser_to) = (
+            slot.winner_to.map(|g| (g, slot.winner_to_top)),
+            slot.loser_to.map(|g| (g, slot.loser_to_top)),
+        );
+
+        // the winners bracket's finalist hasn't lost a set yet, so if they win the
+        // grand final the reset is a bye for them
+        if grand_final && top_won {
+            winner_to = winner_to.map(|(g, _)| (g, true));
+            loser_to = loser_to.map(|(g, _)| (g, false));
+            loser = None;
+        }
+
+        if let Some((guid, top)) = winner_to {
+            self.deliver(guid, top, winner);
+        }
+        if let Some((guid, top)) = loser_to {
+            self.deliver(guid, top, loser);
+        }
+    }
+
+    pub fn set_score(&mut self, guid: Uuid, top_wins: i32, bottom_wins: i32) -> anyhow::Result<()> {
+        let needed = self.wins_needed();
+        let slot = self
+            .slot_mut(guid)
+            .ok_or(anyhow::anyhow!("No bracket slot with id {}.", guid))?;
+        if slot.winner.is_some() {
+            return Err(anyhow::anyhow!("Bracket slot {} is already decided.", guid));
+        }
+        if slot.top.is_none() || slot.bottom.is_none() {
+            return Err(anyhow::anyhow!(
+                "Bracket slot {} is still waiting on entrants.",
+                guid
+            ));
+        }
+        slot.top_wins = top_wins;
+        slot.bottom_wins = bottom_wins;
+        slot.map_scores.clear();
+        slot.map_failed.clear();
+
+        if top_wins >= needed {
+            self.finish(guid, true);
+        } else if bottom_wins >= needed {
+            self.finish(guid, false);
+        }
+        Ok(())
+    }
+
+    pub fn link_match(&mut self, guid: Uuid, match_guid: Uuid) -> anyhow::Result<()> {
+        let slot = self
+            .slot_mut(guid)
+            .ok_or(anyhow::anyhow!("No bracket slot with id {}.", guid))?;
+        slot.match_guid = Some(match_guid);
+        Ok(())
+    }
+
+    /// Links a TA match to the open slot both of whose entrants are in it.
+    fn auto_link(&mut self, match_guid: Uuid, user_ids: &[String]) -> bool {
+        if self
+            .rounds
+            .iter()
+            .flat_map(|r| r.slots.iter())
+            .any(|s| s.match_guid == Some(match_guid) && s.winner.is_none())
+        {
+            return false;
+        }
+
+        let slot = self
+            .rounds
+            .iter_mut()
+            .flat_map(|r| r.slots.iter_mut())
+            .filter(|s| s.winner.is_none() && s.match_guid.is_none())
+            .find(|s| match (&s.top, &s.bottom) {
+                (Some(top), Some(bottom)) => {
+                    user_ids.contains(&top.user_id) && user_ids.contains(&bottom.user_id)
+                }
+                _ => false,
+            });
+        match slot {
+            Some(slot) => {
+                info!("Linked match {} to bracket slot {}", match_guid, slot.guid);
+                slot.match_guid = Some(match_guid);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Counts a finished map towards the set of the slot the match is linked to.
+    fn record_score(
+        &mut self,
+        match_guid: Uuid,
+        user_id: &str,
+        score: i32,
+        completion: CompletionType,
+    ) -> bool {
+        let needed = self.wins_needed();
+        let slot = match self
+            .rounds
+            .iter_mut()
+            .flat_map(|r| r.slots.iter_mut())
+            .find(|s| s.match_guid == Some(match_guid) && s.winner.is_none())
+        {
+            Some(slot) => slot,
+            None => return false,
+        };
+        if !slot.has_entrant(user_id) {
+            return false;
+        }
+        slot.map_scores.insert(user_id.to_string(), score);
+        match completion {
+            CompletionType::Passed => slot.map_failed.remove(user_id),
+            CompletionType::Failed | CompletionType::Quit => {
+                slot.map_failed.insert(user_id.to_string())
+            }
+        };
+
+        // a pass beats a fail, then the higher score wins
+        let result = |user_id: &String| {
+            slot.map_scores
+                .get(user_id)
+                .map(|score| (!slot.map_failed.contains(user_id), *score))
+        };
+        let (top, bottom) = match (&slot.top, &slot.bottom) {
+            (Some(top), Some(bottom)) => (result(&top.user_id), result(&bottom.user_id)),
+            _ => return true,
+        };
+        if let (Some(top), Some(bottom)) = (top, bottom) {
+            slot.map_scores.clear();
+            slot.map_failed.clear();
+            match top.cmp(&bottom) {
+                std::cmp::Ordering::Greater => slot.top_wins += 1,
+                std::cmp::Ordering::Less => slot.bottom_wins += 1,
+                std::cmp::Ordering::Equal => warn!("Map tied in bracket slot {}", slot.guid),
+            }
+            let guid = slot.guid;
+            if slot.top_wins >= needed {
+                self.finish(guid, true);
+            } else if slot.bottom_wins >= needed {
+                self.finish(guid, false);
+            }
+        }
+        true
+    }
+}
+
+impl Observation {
+    pub fn of(state: &TAState, packet: &packet::Packet) -> Option<Self> {
+        let user_id = |guid: &String| {
+            state
+                .players
+                .iter()
+                .find(|p| p.guid == *guid)
+                .map(|p| p.user_id.clone())
+        };
+
+        match &packet.packet {
+            Some(packet::packet::Packet::Event(packet::Event {
+                changed_object:
+                    Some(
+                        packet::event::ChangedObject::MatchCreatedEvent(
+                            packet::event::MatchCreatedEvent { r#match: Some(m) },
+                        )
+                        | packet::event::ChangedObject::MatchUpdatedEvent(
+                            packet::event::MatchUpdatedEvent { r#match: Some(m) },
+                        ),
+                    ),
+            })) => Some(Observation::Match {
+                guid: Uuid::parse_str(&m.guid).ok()?,
+                user_ids: m.associated_users.iter().filter_map(user_id).collect(),
+            }),
+            Some(packet::packet::Packet::Push(packet::Push {
+                data: Some(push::Data::SongFinished(finished)),
+            })) => {
+                let player = finished.player.as_ref()?;
+                Some(Observation::SongFinished {
+                    user_id: player.user_id.clone(),
+                    score: finished.score,
+                    completion: finished.r#type(),
+                    matches: state
+                        .matches
+                        .iter()
+                        .filter(|m| m.associated_users.contains(&player.guid))
+                        .filter_map(|m| Uuid::parse_str(&m.guid).ok())
+                        .collect(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl BracketState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn load(&mut self, config: &Config) -> anyhow::Result<()> {
+        let data = match tokio::fs::read(config.data_path(BRACKET_FILE)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        self.tournament = serde_json::from_slice(&data)?;
+        info!("Loaded bracket");
+        Ok(())
+    }
+
+    pub async fn save(&self, config: &Config) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&config.data_dir).await?;
+        tokio::fs::write(
+            config.data_path(BRACKET_FILE),
+            serde_json::to_vec_pretty(&self.tournament)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub fn tournament_mut(&mut self) -> anyhow::Result<&mut Tournament> {
+        self.tournament
+            .as_mut()
+            .ok_or(anyhow::anyhow!("No bracket has been created."))
+    }
+
+    /// Links new matches to bracket slots and tracks set results.
+    /// Returns whether the bracket changed.
+    pub fn observe(&mut self, observation: Observation) -> bool {
+        let tournament = match self.tournament.as_mut() {
+            Some(tournament) => tournament,
+            None => return false,
+        };
+        match observation {
+            Observation::Match { guid, user_ids } => tournament.auto_link(guid, &user_ids),
+            Observation::SongFinished {
+                user_id,
+                score,
+                completion,
+                matches,
+            } => matches
+                .into_iter()
+                .any(|guid| tournament.record_score(guid, &user_id, score, completion)),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::Path;
+
+use prost::Message as _;
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, warn};
+
+use crate::proto::packet;
+
+/// A packet from the TA server and when it arrived. Captures are a sequence of
+/// these, each length-delimited.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Record {
+    /// Unix time in milliseconds.
+    #[prost(int64, tag = "1")]
+    pub received_at_ms: i64,
+    #[prost(message, optional, tag = "2")]
+    pub packet: Option<packet::Packet>,
+}
+
+/// Appends packets to a capture file as they arrive.
+pub struct Capture {
+    file: tokio::fs::File,
+}
+
+impl Capture {
+    pub async fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Self { file })
+    }
+
+    pub async fn write(&mut self, packet: &packet::Packet) -> anyhow::Result<()> {
+        let record = Record {
+            received_at_ms: chrono::Utc::now().timestamp_millis(),
+            packet: Some(packet.clone()),
+        };
+        self.file
+            .write_all(&record.encode_length_delimited_to_vec())
+            .await?;
+        Ok(())
+    }
+
+    /// Writes out whatever tokio still has buffered and syncs it to disk.
+    pub async fn close(mut self) -> anyhow::Result<()> {
+        self.file.flush().await?;
+        self.file.sync_data().await?;
+        Ok(())
+    }
+}
+
+/// Reads every record in a capture. A record cut short, as the relay was killed
+/// while writing it, ends the capture.
+pub async fn read<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<Record>> {
+    let data = tokio::fs::read(path).await?;
+    let mut buf = data.as_slice();
+    let mut records = vec![];
+    while !buf.is_empty() {
+        match Record::decode_length_delimited(&mut buf) {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                warn!("Capture ends with a broken record, skipping it.");
+                debug!("Error: {}", e);
+                break;
+            }
+        }
+    }
+    Ok(records)
+}
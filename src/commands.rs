@@ -0,0 +1,367 @@
+use async_graphql::{Enum, InputObject};
+use uuid::Uuid;
+
+use crate::{
+    context::Ctx,
+    outbound::MatchChange,
+    proto::{
+        models,
+        packet::{self, command, request},
+    },
+};
+
+/// Gameplay modifiers a song can be played with.
+#[derive(Enum, Clone, Copy, Eq, PartialEq)]
+pub enum Modifier {
+    NoFail,
+    NoBombs,
+    NoArrows,
+    NoObstacles,
+    SlowSong,
+    InstaFail,
+    FailOnClash,
+    BatteryEnergy,
+    FastNotes,
+    FastSong,
+    DisappearingArrows,
+    GhostNotes,
+    StrictAngles,
+    ProMode,
+    ZenMode,
+    SmallCubes,
+    SuperFastSong,
+}
+
+impl Modifier {
+    fn option(self) -> models::gameplay_modifiers::GameOptions {
+        use models::gameplay_modifiers::GameOptions;
+        match self {
+            Modifier::NoFail => GameOptions::NoFail,
+            Modifier::NoBombs => GameOptions::NoBombs,
+            Modifier::NoArrows => GameOptions::NoArrows,
+            Modifier::NoObstacles => GameOptions::NoObstacles,
+            Modifier::SlowSong => GameOptions::SlowSong,
+            Modifier::InstaFail => GameOptions::InstaFail,
+            Modifier::FailOnClash => GameOptions::FailOnClash,
+            Modifier::BatteryEnergy => GameOptions::BatteryEnergy,
+            Modifier::FastNotes => GameOptions::FastNotes,
+            Modifier::FastSong => GameOptions::FastSong,
+            Modifier::DisappearingArrows => GameOptions::DisappearingArrows,
+            Modifier::GhostNotes => GameOptions::GhostNotes,
+            Modifier::StrictAngles => GameOptions::StrictAngles,
+            Modifier::ProMode => GameOptions::ProMode,
+            Modifier::ZenMode => GameOptions::ZenMode,
+            Modifier::SmallCubes => GameOptions::SmallCubes,
+            Modifier::SuperFastSong => GameOptions::SuperFastSong,
+        }
+    }
+}
+
+#[derive(InputObject)]
+pub struct InputPlaySong {
+    /// The level's id, e.g. `custom_level_<hash>`.
+    pub level_id: String,
+    /// 0 for Easy up to 4 for Expert+.
+    pub difficulty: i32,
+    #[graphql(default = "Standard")]
+    pub characteristic: String,
+    #[graphql(default)]
+    pub modifiers: Vec<Modifier>,
+    #[graphql(default)]
+    pub floating_scoreboard: bool,
+    #[graphql(default)]
+    pub stream_sync: bool,
+    #[graphql(default)]
+    pub disable_fail: bool,
+    #[graphql(default)]
+    pub disable_pause: bool,
+}
+
+#[derive(InputObject)]
+pub struct InputModalOption {
+    pub label: String,
+    /// Sent back by the player's game when they pick this option.
+    pub value: String,
+}
+
+#[derive(InputObject)]
+pub struct InputMatchLevel {
+    /// The level's id, e.g. `custom_level_<hash>`.
+    pub level_id: String,
+    #[graphql(default)]
+    pub name: String,
+    /// 0 for Easy up to 4 for Expert+.
+    pub difficulty: i32,
+    #[graphql(default = "Standard")]
+    pub characteristic: String,
+}
+
+/// A request for a song to be downloaded and loaded.
+pub fn load_song(level_id: String, custom_host_url: Option<String>) -> packet::packet::Packet {
+    packet::packet::Packet::Request(packet::Request {
+        r#type: Some(request::Type::LoadSong(request::LoadSong {
+            level_id,
+            custom_host_url: custom_host_url.unwrap_or_default(),
+        })),
+    })
+}
+
+pub fn play_song(song: InputPlaySong) -> packet::packet::Packet {
+    let options = song
+        .modifiers
+        .iter()
+        .fold(0, |options, m| options | m.option() as i32);
+    packet::packet::Packet::Command(packet::Command {
+        r#type: Some(command::Type::PlaySong(command::PlaySong {
+            gameplay_parameters: Some(models::GameplayParameters {
+                beatmap: Some(models::Beatmap {
+                    level_id: song.level_id,
+                    characteristic: Some(models::Characteristic {
+                        serialized_name: song.characteristic,
+                        ..Default::default()
+                    }),
+                    difficulty: song.difficulty,
+                    ..Default::default()
+                }),
+                player_settings: Some(models::PlayerSpecificSettings::default()),
+                gameplay_modifiers: Some(models::GameplayModifiers { options }),
+            }),
+            floating_scoreboard: song.floating_scoreboard,
+            stream_sync: song.stream_sync,
+            disable_fail: song.disable_fail,
+            disable_pause: song.disable_pause,
+            ..Default::default()
+        })),
+    })
+}
+
+pub fn return_to_menu() -> packet::packet::Packet {
+    packet::packet::Packet::Command(packet::Command {
+        r#type: Some(command::Type::ReturnToMenu(true)),
+    })
+}
+
+/// A modal with up to two options. Returns the packet and the modal's id.
+pub fn show_modal(
+    title: String,
+    text: String,
+    can_close: bool,
+    options: Vec<InputModalOption>,
+) -> anyhow::Result<(packet::packet::Packet, Uuid)> {
+    if options.len() > 2 {
+        return Err(anyhow::anyhow!("A modal can have at most 2 options."));
+    }
+    let mut options = options.into_iter().map(|o| models::ModalOption {
+        label: o.label,
+        value: o.value,
+    });
+    let modal_id = Uuid::new_v4();
+    let packet = packet::packet::Packet::Request(packet::Request {
+        r#type: Some(request::Type::ShowModal(request::ShowModal {
+            modal_id: modal_id.to_string(),
+            message_title: title,
+            message_text: text,
+            can_close,
+            option1: options.next(),
+            option2: options.next(),
+        })),
+    });
+    Ok((packet, modal_id))
+}
+
+/// A match on the primary server.
+async fn find_match(ctx: &Ctx, match_id: Uuid) -> anyhow::Result<models::Match> {
+    ctx.ta_state
+        .read()
+        .await
+        .matches
+        .iter()
+        .find(|m| Uuid::parse_str(&m.guid).is_ok_and(|guid| guid == match_id))
+        .cloned()
+        .ok_or(anyhow::anyhow!("No match with id {}.", match_id))
+}
+
+/// Forwards `packet` to every player in a match on the primary server.
+pub async fn send_to_match(
+    ctx: &Ctx,
+    match_id: Uuid,
+    packet: packet::packet::Packet,
+) -> anyhow::Result<()> {
+    let r#match = find_match(ctx, match_id).await?;
+    let players = {
+        let state = ctx.ta_state.read().await;
+        r#match
+            .associated_users
+            .into_iter()
+            .filter(|u| state.players.iter().any(|p| &p.guid == u))
+            .collect::<Vec<_>>()
+    };
+    if players.is_empty() {
+        return Err(anyhow::anyhow!("Match {} has no players.", match_id));
+    }
+
+    ctx.outbound
+        .send(packet::Packet {
+            id: Uuid::new_v4().to_string(),
+            from: "".to_string(),
+            packet: Some(packet::packet::Packet::ForwardingPacket(Box::new(
+                packet::ForwardingPacket {
+                    forward_to: players,
+                    packet: Some(Box::new(packet::Packet {
+                        id: Uuid::new_v4().to_string(),
+                        from: "".to_string(),
+                        packet: Some(packet),
+                    })),
+                },
+            ))),
+        })
+        .await
+}
+
+/// Checks every user is a player or coordinator on the primary server.
+async fn check_users(ctx: &Ctx, users: &[Uuid]) -> anyhow::Result<()> {
+    let state = ctx.ta_state.read().await;
+    for user in users {
+        let known = state
+            .players
+            .iter()
+            .chain(&state.coordinators)
+            .any(|u| Uuid::parse_str(&u.guid).is_ok_and(|guid| guid == *user));
+        if !known {
+            return Err(anyhow::anyhow!(
+                "No player or coordinator with id {}.",
+                user
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Creates a match on the primary server, led by the first coordinator.
+/// Returns once TA has announced it.
+pub async fn create_match(
+    ctx: &Ctx,
+    players: Vec<Uuid>,
+    coordinators: Vec<Uuid>,
+) -> anyhow::Result<Uuid> {
+    if players.is_empty() {
+        return Err(anyhow::anyhow!("A match needs at least one player."));
+    }
+    check_users(ctx, &players).await?;
+    check_users(ctx, &coordinators).await?;
+
+    let guid = Uuid::new_v4();
+    let r#match = models::Match {
+        guid: guid.to_string(),
+        associated_users: players
+            .iter()
+            .chain(&coordinators)
+            .map(|u| u.to_string())
+            .collect(),
+        leader: coordinators
+            .first()
+            .map(|c| c.to_string())
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    ctx.outbound
+        .send_confirmed(MatchChange::Created, r#match)
+        .await?;
+    Ok(guid)
+}
+
+/// Sends a changed match, returning once TA has announced the change.
+async fn update_match(
+    ctx: &Ctx,
+    before: &models::Match,
+    after: models::Match,
+) -> anyhow::Result<()> {
+    ctx.outbound.send_update(before, after).await?;
+    Ok(())
+}
+
+pub async fn add_users_to_match(ctx: &Ctx, match_id: Uuid, users: Vec<Uuid>) -> anyhow::Result<()> {
+    check_users(ctx, &users).await?;
+    let before = find_match(ctx, match_id).await?;
+    let mut r#match = before.clone();
+    for user in users {
+        let user = user.to_string();
+        if !r#match.associated_users.contains(&user) {
+            r#match.associated_users.push(user);
+        }
+    }
+    update_match(ctx, &before, r#match).await
+}
+
+pub async fn remove_users_from_match(
+    ctx: &Ctx,
+    match_id: Uuid,
+    users: Vec<Uuid>,
+) -> anyhow::Result<()> {
+    let before = find_match(ctx, match_id).await?;
+    let mut r#match = before.clone();
+    r#match.associated_users.retain(|u| {
+        !users
+            .iter()
+            .any(|r| Uuid::parse_str(u).is_ok_and(|u| u == *r))
+    });
+    update_match(ctx, &before, r#match).await
+}
+
+pub async fn set_match_level(
+    ctx: &Ctx,
+    match_id: Uuid,
+    level: InputMatchLevel,
+) -> anyhow::Result<()> {
+    let before = find_match(ctx, match_id).await?;
+    let mut r#match = before.clone();
+    r#match.selected_level = Some(models::PreviewBeatmapLevel {
+        level_id: level.level_id,
+        name: level.name,
+        ..Default::default()
+    });
+    r#match.selected_characteristic = Some(models::Characteristic {
+        serialized_name: level.characteristic,
+        ..Default::default()
+    });
+    r#match.selected_difficulty = level.difficulty;
+    update_match(ctx, &before, r#match).await
+}
+
+/// Replaces the relay users in a match with `users`.
+pub async fn set_match_relay_users(
+    ctx: &Ctx,
+    match_id: Uuid,
+    users: Vec<Uuid>,
+) -> anyhow::Result<()> {
+    let before = find_match(ctx, match_id).await?;
+    let mut r#match = before.clone();
+    let relay_users = ctx
+        .ta_state
+        .read()
+        .await
+        .server_users
+        .iter()
+        .filter_map(|u| Uuid::parse_str(&u.guid).ok())
+        .collect::<Vec<_>>();
+    if let Some(user) = users.iter().find(|u| !relay_users.contains(u)) {
+        return Err(anyhow::anyhow!("No relay user with id {}.", user));
+    }
+
+    r#match
+        .associated_users
+        .retain(|u| !Uuid::parse_str(u).is_ok_and(|u| relay_users.contains(&u)));
+    r#match
+        .associated_users
+        .extend(users.iter().map(|u| u.to_string()));
+    update_match(ctx, &before, r#match).await
+}
+
+/// Deletes a match, returning once TA has announced it.
+pub async fn delete_match(ctx: &Ctx, match_id: Uuid) -> anyhow::Result<()> {
+    let r#match = find_match(ctx, match_id).await?;
+    ctx.outbound
+        .send_confirmed(MatchChange::Deleted, r#match)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,686 @@
+use std::{net::IpAddr, path::PathBuf};
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use poem::http::{HeaderName, Method};
+use serde::Deserialize;
+
+use crate::auth::Keys;
+
+/// Command line arguments. Every setting can also be set with the listed
+/// environment variable, or in the config file.
+pub fn command() -> Command {
+    let setting = |name: &'static str, env: &'static str, help: &'static str| {
+        Arg::new(name).long(name).env(env).help(help)
+    };
+
+    Command::new("tars")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("A GraphQL relay server for TournamentAssistant")
+        .arg(setting("config", "TARS_CONFIG", "Path to a TOML config file").short('c'))
+        .arg(setting(
+            "bind",
+            "TARS_BIND",
+            "Address the HTTP server binds to",
+        ))
+        .arg(
+            setting("port", "TARS_PORT", "Port the HTTP server listens on")
+                .short('p')
+                .value_parser(value_parser!(u16)),
+        )
+        .arg(setting(
+            "ta-uri",
+            "TA_WS_URI",
+            "Websocket URI of the TA server's overlay port",
+        ))
+        .arg(setting(
+            "password",
+            "TA_PASSWORD",
+            "Password of the TA server",
+        ))
+        .arg(
+            setting(
+                "servers",
+                "TARS_SERVERS",
+                "Comma separated websocket URIs of more TA servers to relay, using the same password",
+            )
+            .value_delimiter(','),
+        )
+        .arg(
+            Arg::new("discover-servers")
+                .long("discover-servers")
+                .env("TARS_DISCOVER_SERVERS")
+                .action(ArgAction::SetTrue)
+                .help("Also relay the known hosts the TA server tells us about"),
+        )
+        .arg(setting(
+            "rx-name",
+            "TARS_RX_NAME",
+            "Name of the relay user that receives updates",
+        ))
+        .arg(setting(
+            "tx-name",
+            "TARS_TX_NAME",
+            "Name of the relay user that sends updates",
+        ))
+        .arg(setting(
+            "data-dir",
+            "TARS_DATA_DIR",
+            "Directory for map pools, brackets, rosters and recorded scores",
+        ))
+        .arg(setting(
+            "roster",
+            "TA_ROSTER",
+            "Roster file, defaults to roster.json in the data directory",
+        ))
+        .arg(
+            setting(
+                "cors-origins",
+                "TARS_CORS_ORIGINS",
+                "Comma separated origins allowed to make cross-origin requests, * for any",
+            )
+            .value_delimiter(','),
+        )
+        .arg(
+            Arg::new("cors-credentials")
+                .long("cors-credentials")
+                .env("TARS_CORS_CREDENTIALS")
+                .action(ArgAction::SetTrue)
+                .help("Allow cross-origin requests to send cookies and Authorization headers"),
+        )
+        .arg(setting(
+            "keys-file",
+            "TARS_KEYS_FILE",
+            "TOML file of API keys and their roles. Without one, anyone can do anything",
+        ))
+        .arg(setting(
+            "capture",
+            "TARS_CAPTURE",
+            "File to append every packet from the TA server to, for replaying later",
+        ))
+        .arg(setting(
+            "replay",
+            "TARS_REPLAY",
+            "Capture file to replay instead of connecting to the TA server",
+        ))
+        .arg(setting(
+            "replay-speed",
+            "TARS_REPLAY_SPEED",
+            "How fast to replay: realtime, a multiplier like 4x, or step to advance with the stepReplay mutation",
+        ))
+        .arg(
+            setting(
+                "snapshot-interval",
+                "TARS_SNAPSHOT_INTERVAL",
+                "Seconds between snapshots of the relay's state, 0 to disable them",
+            )
+            .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("simulate")
+                .long("simulate")
+                .env("TARS_SIMULATE")
+                .action(ArgAction::SetTrue)
+                .help("Relay made-up matches instead of connecting to the TA server"),
+        )
+        .arg(
+            setting(
+                "simulate-players",
+                "TARS_SIMULATE_PLAYERS",
+                "Number of made-up players to simulate",
+            )
+            .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            setting(
+                "associate",
+                "TARS_ASSOCIATE",
+                "Which relay users are added to new matches",
+            )
+            .value_parser(["all", "none", "rules"]),
+        )
+        .arg(
+            setting("log-format", "TARS_LOG_FORMAT", "Log output format")
+                .value_parser(["full", "compact", "pretty", "json"]),
+        )
+        .arg(
+            Arg::new("no-playground")
+                .long("no-playground")
+                .action(ArgAction::SetTrue)
+                .help("Don't serve /playground and /graphiql"),
+        )
+        .arg(
+            Arg::new("no-record-rts")
+                .long("no-record-rts")
+                .action(ArgAction::SetTrue)
+                .help("Don't write realtime scores to the data directory"),
+        )
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Full,
+    Compact,
+    Pretty,
+    Json,
+}
+
+/// How fast a capture is replayed.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub enum ReplaySpeed {
+    /// Packets keep their captured spacing, divided by this.
+    Timed(f64),
+    /// Packets are only replayed when asked for with `stepReplay`.
+    Stepped,
+}
+
+impl Default for ReplaySpeed {
+    fn default() -> Self {
+        Self::Timed(1.0)
+    }
+}
+
+impl TryFrom<String> for ReplaySpeed {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "realtime" => Ok(Self::Timed(1.0)),
+            "step" => Ok(Self::Stepped),
+            v => match v.trim_end_matches('x').parse::<f64>() {
+                Ok(speed) if speed > 0.0 => Ok(Self::Timed(speed)),
+                _ => Err(format!(
+                    "replay speed {} must be realtime, step, or a multiplier like 4x",
+                    value
+                )),
+            },
+        }
+    }
+}
+
+/// Which relay users (websocket connections such as overlays) are added to new matches.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AssociationMode {
+    /// Every relay user except this relay's TX user.
+    #[default]
+    All,
+    None,
+    /// Only the relay users picked by `rules`.
+    Rules,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Association {
+    pub mode: AssociationMode,
+    pub rules: Vec<AssociationRule>,
+}
+
+/// Adds relay users to the matches with any of the given players or coordinators.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssociationRule {
+    /// Relay users to add, by guid or exact name.
+    pub users: Vec<String>,
+    /// Players or coordinators by guid, exact name or user id. Every match if empty.
+    pub matches_with: Vec<String>,
+}
+
+/// Made-up matches to relay instead of a TA server's.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Simulator {
+    pub enabled: bool,
+    pub players: usize,
+    pub players_per_match: usize,
+    /// Teams to split the players into, 0 for no teams.
+    pub teams: usize,
+    pub song_seconds: u64,
+    /// How far apart the players' accuracies are, e.g. 0.05 for 5%.
+    pub skill_spread: f64,
+    /// Seeds the simulation, so runs can be repeated.
+    pub seed: Option<u64>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            players: 4,
+            players_per_match: 2,
+            teams: 0,
+            song_seconds: 120,
+            skill_spread: 0.05,
+            seed: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    pub playground: bool,
+    pub record_rts: bool,
+}
+
+/// A TA server to relay.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub uri: String,
+    #[serde(default)]
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cors {
+    /// Origins allowed to make cross-origin requests, `*` wildcards are allowed.
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    pub expose_headers: Vec<String>,
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight response, in seconds.
+    pub max_age: i32,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            origins: vec!["*".to_string()],
+            methods: ["GET", "POST", "OPTIONS"].map(String::from).to_vec(),
+            headers: ["Content-Type", "Authorization", "X-Api-Key"]
+                .map(String::from)
+                .to_vec(),
+            expose_headers: vec![],
+            allow_credentials: false,
+            max_age: 86400,
+        }
+    }
+}
+
+/// Limits on what a single client can ask of the relay. 0 disables a limit.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_depth: usize,
+    pub max_complexity: usize,
+    /// GraphQL operations per minute per client, each operation in a batch counts.
+    pub requests_per_minute: u32,
+    /// Subscriptions open at once on a single websocket connection.
+    pub max_subscriptions: usize,
+    /// Reverse proxies whose `X-Forwarded-For` is believed when telling clients
+    /// without a key apart.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: 12,
+            max_complexity: 1000,
+            requests_per_minute: 600,
+            max_subscriptions: 32,
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            playground: true,
+            record_rts: true,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: String,
+    pub port: u16,
+    pub ta_uri: String,
+    pub password: String,
+    /// More TA servers to relay alongside the one at `ta_uri`.
+    pub servers: Vec<ServerConfig>,
+    pub discover_servers: bool,
+    pub rx_name: String,
+    pub tx_name: String,
+    pub data_dir: PathBuf,
+    pub roster: Option<PathBuf>,
+    pub cors: Cors,
+    pub keys_file: Option<PathBuf>,
+    /// Where to append the packets received from the primary server.
+    pub capture: Option<PathBuf>,
+    /// A capture to replay instead of connecting to `ta_uri`.
+    pub replay: Option<PathBuf>,
+    pub replay_speed: ReplaySpeed,
+    pub simulator: Simulator,
+    /// Seconds between snapshots of the relay's state, 0 disables them.
+    pub snapshot_interval: u64,
+    pub association: Association,
+    pub log_format: LogFormat,
+    pub limits: Limits,
+    pub features: Features,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0".to_string(),
+            port: 8080,
+            ta_uri: "".to_string(),
+            password: "".to_string(),
+            servers: vec![],
+            discover_servers: false,
+            rx_name: "TA-Relay-RX".to_string(),
+            tx_name: "TA-Relay-TX".to_string(),
+            data_dir: PathBuf::from("./data"),
+            roster: None,
+            cors: Cors::default(),
+            keys_file: None,
+            capture: None,
+            replay: None,
+            replay_speed: ReplaySpeed::default(),
+            simulator: Simulator::default(),
+            snapshot_interval: 30,
+            association: Association::default(),
+            log_format: LogFormat::default(),
+            limits: Limits::default(),
+            features: Features::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Builds the config from the defaults, the config file, then the environment and arguments.
+    pub fn load(args: &ArgMatches) -> anyhow::Result<Self> {
+        let mut config = match args.get_one::<String>("config") {
+            Some(path) => {
+                let data = std::fs::read_to_string(path)
+                    .map_err(|e| anyhow::anyhow!("Failed to read config file {}: {}", path, e))?;
+                toml::from_str(&data)
+                    .map_err(|e| anyhow::anyhow!("Failed to parse config file {}: {}", path, e))?
+            }
+            None => Config::default(),
+        };
+
+        let string = |name: &str| args.get_one::<String>(name).cloned();
+        string("bind").map(|v| config.bind = v);
+        args.get_one::<u16>("port").map(|v| config.port = *v);
+        string("ta-uri").map(|v| config.ta_uri = v);
+        string("password").map(|v| config.password = v);
+        args.get_many::<String>("servers").map(|v| {
+            config.servers = v
+                .map(|uri| ServerConfig {
+                    uri: uri.clone(),
+                    password: config.password.clone(),
+                })
+                .collect()
+        });
+        if args.get_flag("discover-servers") {
+            config.discover_servers = true;
+        }
+        string("rx-name").map(|v| config.rx_name = v);
+        string("tx-name").map(|v| config.tx_name = v);
+        string("data-dir").map(|v| config.data_dir = PathBuf::from(v));
+        string("roster").map(|v| config.roster = Some(PathBuf::from(v)));
+        args.get_many::<String>("cors-origins")
+            .map(|v| config.cors.origins = v.cloned().collect());
+        if args.get_flag("cors-credentials") {
+            config.cors.allow_credentials = true;
+        }
+        string("keys-file").map(|v| config.keys_file = Some(PathBuf::from(v)));
+        string("capture").map(|v| config.capture = Some(PathBuf::from(v)));
+        string("replay").map(|v| config.replay = Some(PathBuf::from(v)));
+        if let Some(v) = string("replay-speed") {
+            config.replay_speed = ReplaySpeed::try_from(v).map_err(|e| anyhow::anyhow!(e))?;
+        }
+        args.get_one::<u64>("snapshot-interval")
+            .map(|v| config.snapshot_interval = *v);
+        if args.get_flag("simulate") {
+            config.simulator.enabled = true;
+        }
+        args.get_one::<usize>("simulate-players")
+            .map(|v| config.simulator.players = *v);
+        string("associate").map(|v| {
+            config.association.mode = match v.as_str() {
+                "none" => AssociationMode::None,
+                "rules" => AssociationMode::Rules,
+                _ => AssociationMode::All,
+            }
+        });
+        string("log-format").map(|v| {
+            config.log_format = match v.as_str() {
+                "compact" => LogFormat::Compact,
+                "pretty" => LogFormat::Pretty,
+                "json" => LogFormat::Json,
+                _ => LogFormat::Full,
+            }
+        });
+        if args.get_flag("no-playground") {
+            config.features.playground = false;
+        }
+        if args.get_flag("no-record-rts") {
+            config.features.record_rts = false;
+        }
+
+        Ok(config)
+    }
+
+    /// Checks the config, returning every problem found. Nothing is created, the
+    /// data directory is made at startup once the config is known to be good.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+
+        if self.bind.parse::<IpAddr>().is_err() {
+            errors.push(format!("bind address {} is not an IP address", self.bind));
+        }
+        if self.ta_uri.is_empty() {
+            if self.is_live() {
+                errors.push("TA uri not set. Set TA_WS_URI in .env, pass --ta-uri, or set ta_uri in the config file".to_string());
+            }
+        } else if !self.ta_uri.starts_with("ws://") && !self.ta_uri.starts_with("wss://") {
+            errors.push(format!(
+                "TA uri {} must start with ws:// or wss://",
+                self.ta_uri
+            ));
+        }
+        for (i, server) in self.servers.iter().enumerate() {
+            if !server.uri.starts_with("ws://") && !server.uri.starts_with("wss://") {
+                errors.push(format!(
+                    "server uri {} must start with ws:// or wss://",
+                    server.uri
+                ));
+            } else if server.uri == self.ta_uri
+                || self.servers[..i].iter().any(|s| s.uri == server.uri)
+            {
+                errors.push(format!("server {} is listed twice", server.uri));
+            }
+        }
+        if self.rx_name.is_empty() || self.tx_name.is_empty() {
+            errors.push("relay user names must not be empty".to_string());
+        } else if self.rx_name == self.tx_name {
+            errors.push("rx_name and tx_name must be different".to_string());
+        }
+        if self.data_dir.exists() && !self.data_dir.is_dir() {
+            errors.push(format!(
+                "data directory {} is not a directory",
+                self.data_dir.display()
+            ));
+        }
+        let roster = self.roster_path();
+        if !["json", "toml", "csv"].contains(
+            &roster
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default(),
+        ) {
+            errors.push(format!(
+                "roster {} must be a .json, .toml or .csv file",
+                roster.display()
+            ));
+        }
+        if let Some(replay) = &self.replay {
+            if !replay.is_file() {
+                errors.push(format!("replay file {} does not exist", replay.display()));
+            }
+            if self.capture.is_some() {
+                errors.push("capture and replay can't both be set".to_string());
+            }
+        }
+        if self.simulator.enabled {
+            if self.replay.is_some() {
+                errors.push("simulator and replay can't both be enabled".to_string());
+            }
+            errors.extend(self.simulator.validate());
+        }
+        errors.extend(self.association.validate());
+        errors.extend(self.cors.validate());
+        if let Some(Err(e)) = self.keys_file.as_ref().map(|p| Keys::load(p)) {
+            errors.push(e.to_string());
+        }
+
+        errors
+    }
+
+    /// Whether the relay is connected to a real TA server, rather than replaying
+    /// a capture or simulating.
+    pub fn is_live(&self) -> bool {
+        self.replay.is_none() && !self.simulator.enabled
+    }
+
+    /// The server at `ta_uri`.
+    pub fn primary_server(&self) -> ServerConfig {
+        ServerConfig {
+            uri: self.ta_uri.clone(),
+            password: self.password.clone(),
+        }
+    }
+
+    pub fn roster_path(&self) -> PathBuf {
+        self.roster
+            .clone()
+            .unwrap_or(self.data_dir.join("roster.json"))
+    }
+
+    /// A path inside the data directory.
+    pub fn data_path<P: AsRef<std::path::Path>>(&self, path: P) -> PathBuf {
+        self.data_dir.join(path)
+    }
+}
+
+impl Association {
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+
+        if self.mode == AssociationMode::Rules && self.rules.is_empty() {
+            errors.push(
+                "association mode rules needs at least one [[association.rules]]".to_string(),
+            );
+        }
+        if self.rules.iter().any(|r| r.users.is_empty()) {
+            errors.push("every association rule needs at least one user".to_string());
+        }
+
+        errors
+    }
+}
+
+impl Simulator {
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+
+        if self.players == 0 || self.players_per_match == 0 {
+            errors.push("the simulator needs at least one player per match".to_string());
+        }
+        if self.song_seconds == 0 {
+            errors.push("simulated songs must be at least a second long".to_string());
+        }
+        if !(0.0..=0.5).contains(&self.skill_spread) {
+            errors.push(format!(
+                "simulator skill spread {} must be between 0 and 0.5",
+                self.skill_spread
+            ));
+        }
+
+        errors
+    }
+}
+
+impl Cors {
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+
+        if self.origins.is_empty() {
+            errors
+                .push("cors origins must not be empty, use \"*\" to allow any origin".to_string());
+        }
+        for origin in self.origins.iter().filter(|o| *o != "*") {
+            if !origin.starts_with("http://") && !origin.starts_with("https://") {
+                errors.push(format!(
+                    "cors origin {} must start with http:// or https://",
+                    origin
+                ));
+            }
+        }
+        // the origin is echoed back, so "*" with credentials would let any site in with cookies
+        if self.allow_credentials && self.allows_any_origin() {
+            errors.push("cors credentials need explicit origins, not \"*\"".to_string());
+        }
+        for method in &self.methods {
+            if method.parse::<Method>().is_err() {
+                errors.push(format!("cors method {} is not an HTTP method", method));
+            }
+        }
+        for header in self.headers.iter().chain(&self.expose_headers) {
+            if header.parse::<HeaderName>().is_err() {
+                errors.push(format!("cors header {} is not a valid header name", header));
+            }
+        }
+
+        errors
+    }
+
+    pub fn allows_any_origin(&self) -> bool {
+        self.origins.iter().any(|o| o == "*")
+    }
+
+    /// Builds the CORS middleware. Preflight requests are answered by it for every route.
+    pub fn middleware(&self) -> poem::middleware::Cors {
+        let mut cors = poem::middleware::Cors::new()
+            .allow_credentials(self.allow_credentials)
+            .max_age(self.max_age);
+        // poem allows any origin until one is added. Despite the name,
+        // allow_origin_regex takes the same `*` wildcards the config does
+        if !self.allows_any_origin() {
+            cors = self
+                .origins
+                .iter()
+                .fold(cors, |cors, origin| cors.allow_origin_regex(origin));
+        }
+        for method in self.methods.iter().filter_map(|m| m.parse::<Method>().ok()) {
+            cors = cors.allow_method(method);
+        }
+        for header in self
+            .headers
+            .iter()
+            .filter_map(|h| h.parse::<HeaderName>().ok())
+        {
+            cors = cors.allow_header(header);
+        }
+        for header in self
+            .expose_headers
+            .iter()
+            .filter_map(|h| h.parse::<HeaderName>().ok())
+        {
+            cors = cors.expose_header(header);
+        }
+        cors
+    }
+}
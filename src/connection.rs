@@ -6,7 +6,7 @@ use futures_util::{
 use prost::Message as _;
 use tokio::net::TcpStream;
 use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
-use tracing::error;
+use tracing::{debug, error, warn};
 
 #[derive(Debug)]
 pub struct TAConnection {
@@ -16,12 +16,14 @@ pub struct TAConnection {
 }
 
 impl TAConnection {
-    pub async fn connect<T: Into<String>, U: Into<String>>(
+    pub async fn connect<T: Into<String>, U: Into<String>, V: Into<String>>(
         uri: T,
         name: U,
+        password: V,
     ) -> anyhow::Result<Self> {
         let uri = uri.into();
         let name = name.into();
+        let password = password.into();
         let (ws_stream, _) = match tokio_tungstenite::connect_async(uri).await {
             Ok(c) => c,
             Err(_) => {
@@ -43,7 +45,7 @@ impl TAConnection {
             packet: Some(packet::packet::Packet::Request(packet::Request {
                 r#type: Some(packet::request::Type::Connect(packet::request::Connect {
                     user: Some(ws_user.clone()),
-                    password: "".to_string(),
+                    password,
                     client_version: 79, // Server version, should be updated when the server is updated :3
                 })),
             })),
@@ -64,6 +66,22 @@ impl TAConnection {
         })
     }
 
+    /// Connects, retrying with a backoff of up to 30 seconds until it succeeds.
+    pub async fn connect_retrying(uri: &str, name: &str, password: &str) -> Self {
+        let mut backoff = std::time::Duration::from_secs(1);
+        loop {
+            match Self::connect(uri, name, password).await {
+                Ok(con) => return con,
+                Err(e) => {
+                    warn!("Failed to connect to {}, retrying in {:?}.", uri, backoff);
+                    debug!("Error: {}", e);
+                }
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(std::time::Duration::from_secs(30));
+        }
+    }
+
     pub async fn send(&mut self, packet: packet::Packet) -> anyhow::Result<()> {
         self.ws_tx
             .send(Message::Binary(packet.encode_to_vec()))
@@ -71,8 +89,16 @@ impl TAConnection {
         Ok(())
     }
 
-    pub async fn close(self) {
-        drop(self);
+    /// Closes the websocket with a close frame, giving the server a moment to acknowledge it.
+    pub async fn close(mut self) {
+        if let Err(e) = self.ws_tx.close().await {
+            debug!("Failed to close websocket: {}", e);
+            return;
+        }
+        let _ = tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while self.ws_rx.next().await.is_some() {}
+        })
+        .await;
     }
 }
 
@@ -0,0 +1,70 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use carboxyl::Sink;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::{
+    bracket::BracketState, config::Config, health::Health, outbound::Outbound, packets::TAState,
+    playback::RtsUpdate, pool::PoolState, proto::packet, replay::Replay, roster::Roster,
+    servers::Relay, shutdown::Shutdown, structs::GQLOverState, BracketUpdates, OverUpdates,
+    PoolUpdates, TAUpdates,
+};
+
+/// Everything the relay's tasks share. Handed to resolvers as schema data and to
+/// HTTP handlers as endpoint data.
+pub type Ctx = Arc<AppContext>;
+
+pub struct AppContext {
+    pub config: Config,
+
+    /// The state of the primary TA server, at `ta_uri`.
+    pub ta_state: RwLock<TAState>,
+    /// The state of every other relayed TA server, by uri. The map is only
+    /// write-locked to add and remove servers, each state has its own lock.
+    pub server_states: RwLock<BTreeMap<String, Arc<RwLock<TAState>>>>,
+    /// The tasks and TX connections of the other relayed servers, by uri.
+    pub(crate) relays: Mutex<BTreeMap<String, Relay>>,
+    pub over_state: RwLock<GQLOverState>,
+    pub pool_state: RwLock<PoolState>,
+    pub bracket_state: RwLock<BracketState>,
+    pub roster: RwLock<Roster>,
+
+    pub ta_updates: Sink<TAUpdates>,
+    /// Events and pushes from the primary server, as received.
+    pub ta_packets: Sink<packet::Packet>,
+    pub rts_updates: Sink<RtsUpdate>,
+    pub over_updates: Sink<OverUpdates>,
+    pub pool_updates: Sink<PoolUpdates>,
+    pub bracket_updates: Sink<BracketUpdates>,
+
+    pub health: Health,
+    pub shutdown: Shutdown,
+    pub replay: Replay,
+    pub outbound: Outbound,
+}
+
+impl AppContext {
+    pub fn new(config: Config) -> Ctx {
+        let ta_state = TAState::for_server(config.primary_server());
+        Arc::new(Self {
+            config,
+            ta_state: RwLock::new(ta_state),
+            server_states: RwLock::new(BTreeMap::new()),
+            relays: Mutex::new(BTreeMap::new()),
+            over_state: RwLock::new(GQLOverState::default()),
+            pool_state: RwLock::new(PoolState::new()),
+            bracket_state: RwLock::new(BracketState::new()),
+            roster: RwLock::new(Roster::new()),
+            ta_updates: Sink::new(),
+            ta_packets: Sink::new(),
+            rts_updates: Sink::new(),
+            over_updates: Sink::new(),
+            pool_updates: Sink::new(),
+            bracket_updates: Sink::new(),
+            health: Health::new(),
+            shutdown: Shutdown::new(),
+            replay: Replay::new(),
+            outbound: Outbound::new(),
+        })
+    }
+}
@@ -0,0 +1,166 @@
+use futures_util::{stream::BoxStream, SinkExt, Stream, StreamExt};
+use poem::{
+    handler,
+    web::{
+        sse::{Event, SSE},
+        websocket::{CloseCode, Message, WebSocket, WebSocketStream},
+        Data, Query,
+    },
+    IntoResponse, Response,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    context::Ctx,
+    gql::{score_updates, sink_stream},
+    structs::{GQLTAState, Score},
+    TAUpdates,
+};
+
+/// What a feed streams, from the query string. The same filters as the `scores`
+/// subscription, with `matchId` also narrowing the state down to that match.
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedFilter {
+    match_id: Option<Uuid>,
+    user_id: Option<Uuid>,
+    #[serde(default)]
+    delayed: bool,
+}
+
+/// A message on `/events` or `/ws/json`.
+#[derive(Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "lowercase")]
+pub enum FeedMessage {
+    /// The primary server's state, first on connecting, then whenever it changes.
+    State(GQLTAState),
+    Score(Score),
+}
+
+impl FeedMessage {
+    fn kind(&self) -> &'static str {
+        match self {
+            FeedMessage::State(_) => "state",
+            FeedMessage::Score(_) => "score",
+        }
+    }
+}
+
+/// State changes and realtime scores for `filter`, until shutdown.
+pub fn feed(ctx: Ctx, filter: FeedFilter) -> impl Stream<Item = FeedMessage> {
+    let scores = score_updates(ctx.clone(), filter.match_id, filter.user_id, filter.delayed)
+        .filter_map(|update| async move {
+            Score::from_rts(&update.user_guid, &update.score)
+                .ok()
+                .map(FeedMessage::Score)
+        });
+    let shutdown = ctx.shutdown.wait();
+
+    futures_util::stream::select(states(ctx, filter.match_id), scores).take_until(shutdown)
+}
+
+/// The state whenever it changes. Realtime scores don't trigger a TA update, and
+/// other packets don't always change what the feed shows, so only states that
+/// differ from the last one sent are passed on.
+fn states(ctx: Ctx, match_id: Option<Uuid>) -> BoxStream<'static, FeedMessage> {
+    let mut updates = Box::pin(sink_stream(&ctx.ta_updates));
+
+    async_stream::stream! {
+        let mut last = None;
+        loop {
+            match state(&ctx, match_id).await {
+                Ok(state) => {
+                    let json = serde_json::to_value(&state).ok();
+                    if json != last {
+                        last = json;
+                        yield FeedMessage::State(state);
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to read state for a feed.");
+                    debug!("Error: {}", e);
+                }
+            }
+            loop {
+                match updates.next().await {
+                    Some(TAUpdates::NewState) => break,
+                    Some(_) => continue,
+                    None => return,
+                }
+            }
+        }
+    }
+    .boxed()
+}
+
+async fn state(ctx: &Ctx, match_id: Option<Uuid>) -> anyhow::Result<GQLTAState> {
+    let state = ctx.ta_state.read().await.into_gql().await?.without_scores();
+    Ok(match match_id {
+        Some(id) => state.only_match(id),
+        None => state,
+    })
+}
+
+/// The feed as Server-Sent Events, with each message's `type` as the event name
+/// and its `data` as JSON.
+#[handler]
+pub async fn events_route(ctx: Data<&Ctx>, Query(filter): Query<FeedFilter>) -> Response {
+    SSE::new(feed(ctx.clone(), filter).filter_map(|message| async move {
+        let kind = message.kind();
+        let data = match message {
+            FeedMessage::State(state) => serde_json::to_string(&state),
+            FeedMessage::Score(score) => serde_json::to_string(&score),
+        };
+        data.ok().map(|data| Event::message(data).event_type(kind))
+    }))
+    .keep_alive(std::time::Duration::from_secs(15))
+    .into_response()
+}
+
+/// The feed over a websocket, one JSON message per text frame. Anything the
+/// client sends is ignored.
+#[handler]
+pub async fn json_ws_route(
+    ctx: Data<&Ctx>,
+    Query(filter): Query<FeedFilter>,
+    websocket: WebSocket,
+) -> Response {
+    let ctx = ctx.clone();
+    websocket
+        .on_upgrade(move |stream| serve_json(ctx, filter, stream))
+        .into_response()
+}
+
+async fn serve_json(ctx: Ctx, filter: FeedFilter, stream: WebSocketStream) {
+    let (mut sink, mut stream) = stream.split();
+    let mut messages = Box::pin(feed(ctx.clone(), filter));
+
+    loop {
+        tokio::select! {
+            message = messages.next() => {
+                let Some(message) = message else {
+                    break;
+                };
+                let Ok(json) = serde_json::to_string(&message) else {
+                    continue;
+                };
+                if sink.send(Message::Text(json)).await.is_err() {
+                    return;
+                }
+            }
+            msg = stream.next() => {
+                if matches!(msg, Some(Ok(Message::Close(_))) | None | Some(Err(_))) {
+                    return;
+                }
+            }
+        }
+    }
+
+    if ctx.shutdown.is_triggered() {
+        let _ = sink
+            .send(Message::close_with(CloseCode::Away, "Server shutting down"))
+            .await;
+    }
+}
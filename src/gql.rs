@@ -1,32 +1,606 @@
-use async_graphql::{Context, Object};
+use async_graphql::{Context, Object, Schema, SchemaBuilder, Subscription};
+use carboxyl::Sink;
+use futures_util::{stream::BoxStream, Stream, StreamExt};
 
 use crate::{
-    structs::{GQLTAState, Match},
-    TA_STATE,
+    auth::{Role, RoleGuard},
+    bracket::{read_seeds, seed_path, BracketKind, InputSeed, Tournament},
+    commands::{self, InputMatchLevel, InputModalOption, InputPlaySong},
+    config::ReplaySpeed,
+    context::Ctx,
+    limits::QueryLimits,
+    metrics,
+    playback::{delay_scores, RtsUpdate, StreamDelay},
+    pool::{GQLPickBan, InputPoolMap, MapPool, PickBanAction},
+    replay::GQLReplay,
+    servers, shutdown,
+    structs::{GQLServer, GQLServerInfo, GQLTAState, KnownHost, Match, Score, User},
+    BracketUpdates, PoolUpdates,
 };
 use uuid::Uuid;
 
+pub type TarsSchema = Schema<Query, Mutation, Subscription>;
+
+/// The relay's schema with its context, limits, metrics and shutdown handling,
+/// ready for more data or extensions before `finish`.
+pub fn schema_builder(ctx: &Ctx) -> SchemaBuilder<Query, Mutation, Subscription> {
+    Schema::build(Query, Mutation, Subscription)
+        .data(ctx.clone())
+        .extension(QueryLimits::new(ctx.config.limits.clone()))
+        .extension(metrics::GraphQLMetrics)
+        .extension(shutdown::DrainSubscriptions(ctx.shutdown.clone()))
+}
+
 pub struct Query;
 
 #[Object]
 impl Query {
-    async fn state<'ctx>(&self, _ctx: &Context<'ctx>) -> anyhow::Result<GQLTAState> {
-        TA_STATE.read().await.into_gql().await
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
+    async fn state<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        #[graphql(desc = "Uri or name of the server, the primary server if not given.")]
+        server: Option<String>,
+    ) -> anyhow::Result<GQLTAState> {
+        servers::state_gql(app(ctx), server.as_deref()).await
     }
 
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
     async fn match_by_id<'ctx>(
         &self,
-        _ctx: &Context<'ctx>,
+        ctx: &Context<'ctx>,
         id: Uuid,
     ) -> anyhow::Result<Option<Match>> {
-        TA_STATE.read().await.get_single_match_gql(id).await
+        servers::match_gql(app(ctx), id).await
+    }
+
+    /// The TA server's name and settings.
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
+    async fn server<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        #[graphql(desc = "Uri or name of the server, the primary server if not given.")]
+        server: Option<String>,
+    ) -> anyhow::Result<GQLServerInfo> {
+        servers::with_state(app(ctx), server.as_deref(), |s| s.server_info_gql()).await
+    }
+
+    /// The other TA servers the server knows about.
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn known_hosts<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        #[graphql(desc = "Uri or name of the server, the primary server if not given.")]
+        server: Option<String>,
+    ) -> anyhow::Result<Vec<KnownHost>> {
+        servers::with_state(app(ctx), server.as_deref(), |s| s.known_hosts_gql()).await
+    }
+
+    /// The overlay and relay connections on the server, including this relay's own.
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn relay_users<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        #[graphql(desc = "Uri or name of the server, the primary server if not given.")]
+        server: Option<String>,
+    ) -> anyhow::Result<Vec<User>> {
+        servers::with_state(app(ctx), server.as_deref(), |s| s.relay_users_gql()).await
+    }
+
+    /// Every relayed TA server.
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
+    async fn servers<'ctx>(&self, ctx: &Context<'ctx>) -> Vec<GQLServer> {
+        servers::servers_gql(app(ctx)).await
+    }
+
+    /// How far the replay has got, if the relay is replaying a capture.
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
+    async fn replay<'ctx>(&self, ctx: &Context<'ctx>) -> Option<GQLReplay> {
+        let app = app(ctx);
+        app.config
+            .replay
+            .as_ref()
+            .map(|_| app.replay.progress_gql(app.config.replay_speed))
+    }
+
+    /// The matches on every relayed TA server.
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
+    async fn all_matches<'ctx>(&self, ctx: &Context<'ctx>) -> anyhow::Result<Vec<Match>> {
+        servers::all_matches_gql(app(ctx)).await
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
+    async fn pools<'ctx>(&self, ctx: &Context<'ctx>) -> Vec<MapPool> {
+        app(ctx).pool_state.read().await.pools.clone()
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
+    async fn pick_ban<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+    ) -> anyhow::Result<Option<GQLPickBan>> {
+        app(ctx).pool_state.read().await.get_pick_ban_gql(match_id)
     }
 
-    // async fn page<'ctx>(&self, _ctx: &Context<'ctx>) -> GQLOverState {
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
+    async fn bracket<'ctx>(&self, ctx: &Context<'ctx>) -> Option<Tournament> {
+        app(ctx).bracket_state.read().await.tournament.clone()
+    }
+
+    // async fn page<'ctx>(&self, ctx: &Context<'ctx>) -> GQLOverState {
     //     OVER_STATE.read().await.clone()
     // }
 }
 
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn create_pool<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        name: String,
+        maps: Vec<InputPoolMap>,
+    ) -> anyhow::Result<MapPool> {
+        let mut state = app(ctx).pool_state.write().await;
+        let pool = state.add_pool(name, maps.into_iter().map(|m| m.into_pool_map()).collect())?;
+        state.save(&app(ctx).config).await?;
+        app(ctx).pool_updates.send(PoolUpdates::NewPools);
+        Ok(pool)
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn delete_pool<'ctx>(&self, ctx: &Context<'ctx>, id: Uuid) -> anyhow::Result<bool> {
+        let mut state = app(ctx).pool_state.write().await;
+        state.remove_pool(id)?;
+        state.save(&app(ctx).config).await?;
+        app(ctx).pool_updates.send(PoolUpdates::NewPools);
+        Ok(true)
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn set_match_pool<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+        pool_id: Uuid,
+    ) -> anyhow::Result<Option<GQLPickBan>> {
+        let mut state = app(ctx).pool_state.write().await;
+        state.set_match_pool(match_id, pool_id)?;
+        state.save(&app(ctx).config).await?;
+        app(ctx)
+            .pool_updates
+            .send(PoolUpdates::NewPickBan(match_id));
+        state.get_pick_ban_gql(match_id)
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn pick_map<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+        label: String,
+        by: Option<Uuid>,
+    ) -> anyhow::Result<Option<GQLPickBan>> {
+        let mut state = app(ctx).pool_state.write().await;
+        state.apply(match_id, PickBanAction::Pick, label, by)?;
+        state.save(&app(ctx).config).await?;
+        app(ctx)
+            .pool_updates
+            .send(PoolUpdates::NewPickBan(match_id));
+        state.get_pick_ban_gql(match_id)
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn ban_map<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+        label: String,
+        by: Option<Uuid>,
+    ) -> anyhow::Result<Option<GQLPickBan>> {
+        let mut state = app(ctx).pool_state.write().await;
+        state.apply(match_id, PickBanAction::Ban, label, by)?;
+        state.save(&app(ctx).config).await?;
+        app(ctx)
+            .pool_updates
+            .send(PoolUpdates::NewPickBan(match_id));
+        state.get_pick_ban_gql(match_id)
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn undo_pick_ban<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+    ) -> anyhow::Result<Option<GQLPickBan>> {
+        let mut state = app(ctx).pool_state.write().await;
+        state.undo(match_id)?;
+        state.save(&app(ctx).config).await?;
+        app(ctx)
+            .pool_updates
+            .send(PoolUpdates::NewPickBan(match_id));
+        state.get_pick_ban_gql(match_id)
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn reset_pick_ban<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+    ) -> anyhow::Result<Option<GQLPickBan>> {
+        let mut state = app(ctx).pool_state.write().await;
+        state.reset(match_id)?;
+        state.save(&app(ctx).config).await?;
+        app(ctx)
+            .pool_updates
+            .send(PoolUpdates::NewPickBan(match_id));
+        state.get_pick_ban_gql(match_id)
+    }
+
+    /// Creates a bracket from either `seeds` or a `.csv`/`.json` file in the data directory.
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn create_bracket<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        name: String,
+        kind: BracketKind,
+        best_of: i32,
+        seeds: Option<Vec<InputSeed>>,
+        seed_file: Option<String>,
+    ) -> anyhow::Result<Tournament> {
+        let seeds = match (seeds, seed_file) {
+            (Some(seeds), None) => seeds.into_iter().map(|s| s.into_seed()).collect(),
+            (None, Some(file)) => {
+                let path = seed_path(&app(ctx).config, &file).await?;
+                read_seeds(&path.to_string_lossy()).await?
+            }
+            _ => return Err(anyhow::anyhow!("Pass exactly one of seeds or seedFile.")),
+        };
+        let tournament = Tournament::new(name, kind, best_of, seeds)?;
+
+        let mut state = app(ctx).bracket_state.write().await;
+        state.tournament = Some(tournament.clone());
+        state.save(&app(ctx).config).await?;
+        app(ctx).bracket_updates.send(BracketUpdates::NewBracket);
+        Ok(tournament)
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Admin)")]
+    async fn delete_bracket<'ctx>(&self, ctx: &Context<'ctx>) -> anyhow::Result<bool> {
+        let mut state = app(ctx).bracket_state.write().await;
+        state.tournament = None;
+        state.save(&app(ctx).config).await?;
+        app(ctx).bracket_updates.send(BracketUpdates::NewBracket);
+        Ok(true)
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn link_bracket_match<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        slot_id: Uuid,
+        match_id: Uuid,
+    ) -> anyhow::Result<Tournament> {
+        let mut state = app(ctx).bracket_state.write().await;
+        let tournament = state.tournament_mut()?;
+        tournament.link_match(slot_id, match_id)?;
+        let tournament = tournament.clone();
+        state.save(&app(ctx).config).await?;
+        app(ctx).bracket_updates.send(BracketUpdates::NewBracket);
+        Ok(tournament)
+    }
+
+    /// Overrides the set score of a slot, advancing the winner once it is decided.
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn set_bracket_score<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        slot_id: Uuid,
+        top_wins: i32,
+        bottom_wins: i32,
+    ) -> anyhow::Result<Tournament> {
+        let mut state = app(ctx).bracket_state.write().await;
+        let tournament = state.tournament_mut()?;
+        tournament.set_score(slot_id, top_wins, bottom_wins)?;
+        let tournament = tournament.clone();
+        state.save(&app(ctx).config).await?;
+        app(ctx).bracket_updates.send(BracketUpdates::NewBracket);
+        Ok(tournament)
+    }
+
+    /// Replays the next `packets` packets of a stepped replay.
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn step_replay<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        #[graphql(default = 1)] packets: usize,
+    ) -> async_graphql::Result<bool> {
+        let app = app(ctx);
+        if app.config.replay_speed != ReplaySpeed::Stepped || app.config.replay.is_none() {
+            return Err("The relay isn't running a stepped replay".into());
+        }
+        app.replay.step(packets);
+        Ok(true)
+    }
+
+    /// Has the match's players download and load a level.
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn load_song<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+        level_id: String,
+        custom_host_url: Option<String>,
+    ) -> anyhow::Result<bool> {
+        let packet = commands::load_song(level_id, custom_host_url);
+        commands::send_to_match(app(ctx), match_id, packet).await?;
+        Ok(true)
+    }
+
+    /// Starts a level for the match's players. It should be loaded first.
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn play_song<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+        song: InputPlaySong,
+    ) -> anyhow::Result<bool> {
+        commands::send_to_match(app(ctx), match_id, commands::play_song(song)).await?;
+        Ok(true)
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn return_to_menu<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+    ) -> anyhow::Result<bool> {
+        commands::send_to_match(app(ctx), match_id, commands::return_to_menu()).await?;
+        Ok(true)
+    }
+
+    /// Shows the match's players a modal, returning its id.
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn show_modal<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+        title: String,
+        text: String,
+        #[graphql(default = true)] can_close: bool,
+        #[graphql(default)] options: Vec<InputModalOption>,
+    ) -> anyhow::Result<Uuid> {
+        let (packet, modal_id) = commands::show_modal(title, text, can_close, options)?;
+        commands::send_to_match(app(ctx), match_id, packet).await?;
+        Ok(modal_id)
+    }
+
+    /// Creates a match on the primary server, led by the first coordinator, and
+    /// returns it once TA has announced it.
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn create_match<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        players: Vec<Uuid>,
+        #[graphql(default)] coordinators: Vec<Uuid>,
+    ) -> anyhow::Result<Option<Match>> {
+        let id = commands::create_match(app(ctx), players, coordinators).await?;
+        servers::match_gql(app(ctx), id).await
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn add_users_to_match<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+        users: Vec<Uuid>,
+    ) -> anyhow::Result<Option<Match>> {
+        commands::add_users_to_match(app(ctx), match_id, users).await?;
+        servers::match_gql(app(ctx), match_id).await
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn remove_users_from_match<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+        users: Vec<Uuid>,
+    ) -> anyhow::Result<Option<Match>> {
+        commands::remove_users_from_match(app(ctx), match_id, users).await?;
+        servers::match_gql(app(ctx), match_id).await
+    }
+
+    /// Selects the level the match plays next.
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn set_match_level<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+        level: InputMatchLevel,
+    ) -> anyhow::Result<Option<Match>> {
+        commands::set_match_level(app(ctx), match_id, level).await?;
+        servers::match_gql(app(ctx), match_id).await
+    }
+
+    /// Sets which relay users, such as overlays, are in the match.
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn set_match_relay_users<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+        users: Vec<Uuid>,
+    ) -> anyhow::Result<Option<Match>> {
+        commands::set_match_relay_users(app(ctx), match_id, users).await?;
+        servers::match_gql(app(ctx), match_id).await
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Producer)")]
+    async fn delete_match<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+    ) -> anyhow::Result<bool> {
+        commands::delete_match(app(ctx), match_id).await?;
+        Ok(true)
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Realtime scores, optionally only for one match or player.
+    ///
+    /// With `delayed`, each score is held back by the player's stream delay, so
+    /// overlays stay in sync with the player's stream instead of running ahead.
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
+    async fn scores<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Option<Uuid>,
+        user_id: Option<Uuid>,
+        #[graphql(default)] delayed: bool,
+    ) -> impl Stream<Item = async_graphql::Result<Score>> {
+        score_updates(app(ctx).clone(), match_id, user_id, delayed)
+            .map(|update| Ok(Score::from_rts(&update.user_guid, &update.score)?))
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
+    async fn pick_ban<'ctx>(
+        &self,
+        ctx: &Context<'ctx>,
+        match_id: Uuid,
+    ) -> impl Stream<Item = async_graphql::Result<Option<GQLPickBan>>> {
+        let app = app(ctx).clone();
+        let mut updates = Box::pin(sink_stream(&app.pool_updates));
+
+        async_stream::stream! {
+            yield pick_ban(&app, match_id).await;
+            while let Some(update) = updates.next().await {
+                match update {
+                    PoolUpdates::NewPickBan(id) if id == match_id => {
+                        yield pick_ban(&app, match_id).await;
+                    }
+                    PoolUpdates::NewPools => {
+                        yield pick_ban(&app, match_id).await;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    #[graphql(guard = "RoleGuard::new(Role::Overlay)")]
+    async fn bracket<'ctx>(&self, ctx: &Context<'ctx>) -> impl Stream<Item = Option<Tournament>> {
+        let app = app(ctx).clone();
+        let mut updates = Box::pin(sink_stream(&app.bracket_updates));
+
+        async_stream::stream! {
+            yield app.bracket_state.read().await.tournament.clone();
+            while let Some(update) = updates.next().await {
+                if let BracketUpdates::NewBracket = update {
+                    yield app.bracket_state.read().await.tournament.clone();
+                }
+            }
+        }
+    }
+}
+
+/// The relay's context, which every schema is built with.
+pub fn app<'a>(ctx: &Context<'a>) -> &'a Ctx {
+    ctx.data_unchecked::<Ctx>()
+}
+
+/// Realtime score updates, optionally only for one match or player and held
+/// back by the player's stream delay.
+pub fn score_updates(
+    app: Ctx,
+    match_id: Option<Uuid>,
+    user_id: Option<Uuid>,
+    delayed: bool,
+) -> BoxStream<'static, RtsUpdate> {
+    let filter_app = app.clone();
+    let updates = sink_stream(&app.rts_updates).filter(move |update| {
+        let update = update.clone();
+        let app = filter_app.clone();
+        async move { score_filter(&app, &update, match_id, user_id).await }
+    });
+    match delayed {
+        true => delay_scores(updates, move |user_guid| {
+            let app = app.clone();
+            async move { stream_delay(&app, user_guid).await }
+        })
+        .boxed(),
+        false => updates.boxed(),
+    }
+}
+
+async fn score_filter(
+    app: &Ctx,
+    update: &RtsUpdate,
+    match_id: Option<Uuid>,
+    user_id: Option<Uuid>,
+) -> bool {
+    if user_id.is_some_and(|id| id.to_string() != update.user_guid) {
+        return false;
+    }
+    match match_id {
+        Some(id) => servers::find(app, |state| {
+            state
+                .matches
+                .iter()
+                .find(|m| m.guid == id.to_string())
+                .map(|m| m.associated_users.contains(&update.user_guid))
+        })
+        .await
+        .unwrap_or(false),
+        None => true,
+    }
+}
+
+async fn stream_delay(app: &Ctx, user_guid: String) -> StreamDelay {
+    servers::find(app, |state| {
+        state
+            .players
+            .iter()
+            .find(|p| p.guid == user_guid)
+            .map(|p| StreamDelay {
+                delay_ms: p.stream_delay_ms,
+                sync_start_ms: p.stream_sync_start_ms,
+            })
+    })
+    .await
+    .unwrap_or_default()
+}
+
+async fn pick_ban(app: &Ctx, match_id: Uuid) -> async_graphql::Result<Option<GQLPickBan>> {
+    Ok(app.pool_state.read().await.get_pick_ban_gql(match_id)?)
+}
+
+/// Turns a carboxyl sink into an async stream of its updates.
+///
+/// carboxyl only offers blocking iterators, so updates are forwarded into a
+/// tokio channel for as long as the returned stream is alive.
+pub fn sink_stream<T>(sink: &Sink<T>) -> impl Stream<Item = T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let forward = sink.stream().map(move |update| {
+        let _ = tx.send(update);
+    });
+
+    async_stream::stream! {
+        let _forward = forward;
+        while let Some(update) = rx.recv().await {
+            yield update;
+        }
+    }
+}
+
 // pub struct Mutation;
 
 // #[juniper::graphql_object(context = Context)]
@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+
+use poem::{handler, http::StatusCode, web::Data, web::Json, IntoResponse};
+use serde::Serialize;
+
+use crate::context::Ctx;
+
+/// What the health endpoints report on.
+pub struct Health {
+    started_at: std::time::Instant,
+    ta_live: AtomicBool,
+    /// Unix time in milliseconds of the last packet from the TA server, 0 if none yet.
+    last_packet_ms: AtomicI64,
+    /// The TA server's name once connected, kept here so the health checks never
+    /// wait on the state lock.
+    server_name: std::sync::RwLock<Option<String>>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            started_at: std::time::Instant::now(),
+            ta_live: AtomicBool::new(false),
+            last_packet_ms: AtomicI64::new(0),
+            server_name: std::sync::RwLock::new(None),
+        }
+    }
+
+    pub fn set_ta_live(&self, live: bool) {
+        self.ta_live.store(live, Ordering::SeqCst);
+    }
+
+    pub fn packet_received(&self) {
+        self.last_packet_ms
+            .store(chrono::Utc::now().timestamp_millis(), Ordering::SeqCst);
+    }
+
+    pub fn set_server_name(&self, name: Option<&str>) {
+        let mut server_name = self.server_name.write().unwrap_or_else(|e| e.into_inner());
+        if server_name.as_deref() != name {
+            *server_name = name.map(str::to_string);
+        }
+    }
+
+    fn server_name(&self) -> Option<String> {
+        self.server_name
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HealthResponse {
+    status: &'static str,
+    /// The TA server's name, once connected.
+    server: Option<String>,
+    uptime_seconds: u64,
+    /// RFC 3339 time of the last packet from the TA server.
+    last_packet_at: Option<String>,
+}
+
+/// Reports on the relay, answering 503 if `readiness` is asked for and the relay isn't ready.
+fn health(ctx: &Ctx, readiness: bool) -> impl IntoResponse {
+    let server = ctx.health.server_name();
+    let ready = !readiness
+        || (ctx.health.ta_live.load(Ordering::SeqCst)
+            && server.is_some()
+            && !ctx.shutdown.is_triggered());
+    let health = HealthResponse {
+        status: match ready {
+            true => "ok",
+            false => "not ready",
+        },
+        server,
+        uptime_seconds: ctx.health.started_at.elapsed().as_secs(),
+        last_packet_at: match ctx.health.last_packet_ms.load(Ordering::SeqCst) {
+            0 => None,
+            ms => chrono::DateTime::from_timestamp_millis(ms).map(|t| t.to_rfc3339()),
+        },
+    };
+
+    match ready {
+        true => Json(health).with_status(StatusCode::OK),
+        false => Json(health).with_status(StatusCode::SERVICE_UNAVAILABLE),
+    }
+}
+
+/// Whether the process is up. Always OK while the HTTP server answers.
+#[handler]
+pub async fn healthz(ctx: Data<&Ctx>) -> impl IntoResponse {
+    health(&ctx, false)
+}
+
+/// Whether the relay is connected to TA and has received its state.
+#[handler]
+pub async fn readyz(ctx: Data<&Ctx>) -> impl IntoResponse {
+    health(&ctx, true)
+}
@@ -0,0 +1,237 @@
+use std::{net::IpAddr, sync::Arc, time::Instant};
+
+use async_graphql::{
+    http::{playground_source, GraphQLPlaygroundConfig, GraphiQLSource, ALL_WEBSOCKET_PROTOCOLS},
+    BatchRequest,
+};
+use async_graphql_poem::{
+    GraphQLBatchRequest, GraphQLBatchResponse, GraphQLProtocol, GraphQLWebSocket,
+};
+use futures_util::{SinkExt, StreamExt};
+use poem::{
+    get, handler,
+    http::StatusCode,
+    listener::TcpListener,
+    web::{
+        websocket::{CloseCode, Message, WebSocket},
+        Data,
+    },
+    Endpoint, EndpointExt, IntoResponse, Request, Response, Route,
+};
+use tracing::{debug, error, info, warn, Instrument};
+
+use crate::{
+    auth::{self, ClientId, Keys, Role},
+    context::Ctx,
+    feed,
+    gql::{schema_builder, TarsSchema},
+    health,
+    limits::{self, RateLimiter, SubscriptionCount},
+    metrics, passthrough, rest,
+};
+
+#[handler]
+async fn graphiql_route() -> Response {
+    Response::builder()
+        .content_type("text/html; charset=utf-8")
+        .body(
+            GraphiQLSource::build()
+                .endpoint("/graphql")
+                .subscription_endpoint("/graphql/ws")
+                .finish(),
+        )
+}
+
+#[handler]
+async fn playground_route() -> Response {
+    Response::builder()
+        .content_type("text/html; charset=utf-8")
+        .body(playground_source(
+            GraphQLPlaygroundConfig::new("/graphql").subscription_endpoint("/graphql/ws"),
+        ))
+}
+
+#[handler]
+async fn metrics_route() -> Response {
+    match metrics::render() {
+        Ok(body) => Response::builder()
+            .content_type("text/plain; version=0.0.4")
+            .body(body),
+        Err(e) => {
+            warn!("Failed to render metrics.");
+            debug!("Error: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into()
+        }
+    }
+}
+
+pub(crate) fn client_id(req: &Request) -> String {
+    req.extensions()
+        .get::<ClientId>()
+        .map(|c| c.0.clone())
+        .unwrap_or_default()
+}
+
+#[handler]
+async fn graphql_route(
+    schema: Data<&TarsSchema>,
+    limiter: Data<&Arc<RateLimiter>>,
+    req: &Request,
+    gql_req: GraphQLBatchRequest,
+) -> Response {
+    let mut gql_req = gql_req.0;
+    let cost = match &gql_req {
+        BatchRequest::Single(_) => 1,
+        BatchRequest::Batch(requests) => requests.len(),
+    };
+    if let Err(retry_after) = limiter.check(&client_id(req), cost).await {
+        let mut resp = GraphQLBatchResponse(
+            async_graphql::Response::from_errors(vec![limits::rate_limited(retry_after)]).into(),
+        )
+        .into_response();
+        resp.set_status(StatusCode::TOO_MANY_REQUESTS);
+        resp.headers_mut()
+            .insert("Retry-After", retry_after.as_secs().max(1).into());
+        return resp;
+    }
+
+    if let Some(role) = req.extensions().get::<Role>() {
+        gql_req = gql_req.data(*role);
+    }
+    GraphQLBatchResponse(schema.execute_batch(gql_req).await).into_response()
+}
+
+#[handler]
+async fn graphql_ws_route(
+    ctx: Data<&Ctx>,
+    schema: Data<&TarsSchema>,
+    keys: Data<&Keys>,
+    limiter: Data<&Arc<RateLimiter>>,
+    req: &Request,
+    protocol: GraphQLProtocol,
+    websocket: WebSocket,
+) -> Response {
+    if let Err(retry_after) = limiter.check(&client_id(req), 1).await {
+        return Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header("Retry-After", retry_after.as_secs().max(1))
+            .body("Rate limited");
+    }
+
+    let shutdown = ctx.shutdown.clone();
+    let schema = schema.clone();
+    let keys = keys.clone();
+    let role = req.extensions().get::<Role>().copied();
+    let client = ClientId(client_id(req));
+
+    websocket
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |stream| async move {
+            let (mut sink, stream) = stream.split();
+            // stop reading shortly after shutdown starts, once the drained
+            // subscriptions have sent their completions
+            let draining = shutdown.wait();
+            let stream = stream.take_until(async {
+                draining.await;
+                tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+            });
+
+            GraphQLWebSocket::new_with_pair(&mut sink, stream, schema, protocol)
+                .on_connection_init(move |payload| async move {
+                    let mut data = auth::connection_init(&keys, role, client, &payload)?;
+                    data.insert(SubscriptionCount::default());
+                    Ok(data)
+                })
+                .serve()
+                .await;
+
+            if shutdown.is_triggered() {
+                let _ = sink
+                    .send(Message::close_with(CloseCode::Away, "Server shutting down"))
+                    .await;
+            }
+        })
+        .into_response()
+}
+
+/// Logs each request and its response like poem's `Tracing`, but with just the path:
+/// the query string can hold an API key.
+async fn trace<E: Endpoint>(ep: Arc<E>, req: Request) -> poem::Result<Response> {
+    let span = tracing::info_span!(
+        "request",
+        remote_addr = %req.remote_addr(),
+        version = ?req.version(),
+        method = %req.method(),
+        path = %req.original_uri().path(),
+    );
+
+    async move {
+        let now = Instant::now();
+        match ep.call(req).await {
+            Ok(resp) => {
+                let resp = resp.into_response();
+                info!(status = %resp.status(), duration = ?now.elapsed(), "response");
+                Ok(resp)
+            }
+            Err(err) => {
+                info!(status = %err.status(), error = %err, duration = ?now.elapsed(), "error");
+                Err(err)
+            }
+        }
+    }
+    .instrument(span)
+    .await
+}
+
+/// The relay's API, with auth, rate limits and CORS applied.
+pub fn routes(ctx: &Ctx, keys: Keys) -> impl Endpoint {
+    let limiter = Arc::new(RateLimiter::new(ctx.config.limits.requests_per_minute));
+    let mut app = Route::new()
+        .at("/graphql", get(graphql_route).post(graphql_route))
+        .at("/graphql/ws", get(graphql_ws_route))
+        .at("/ws/protobuf", get(passthrough::passthrough_route))
+        .at("/ws/json", get(feed::json_ws_route))
+        .at("/events", get(feed::events_route))
+        .nest("/api/v1", rest::routes())
+        .at("/metrics", get(metrics_route))
+        .at("/healthz", get(health::healthz))
+        .at("/readyz", get(health::readyz));
+    if ctx.config.features.playground {
+        app = app
+            .at("/graphiql", graphiql_route)
+            .at("/playground", playground_route);
+    }
+
+    let schema = schema_builder(ctx)
+        .extension(limits::WebSocketRateLimit::new(limiter.clone()))
+        .finish();
+    let trusted_proxies: Arc<[IpAddr]> = ctx.config.limits.trusted_proxies.clone().into();
+    app.data(ctx.clone())
+        .data(schema)
+        .data(keys.clone())
+        .data(limiter)
+        .around(move |ep, req| auth::middleware(ep, req, keys.clone(), trusted_proxies.clone()))
+        .around(trace)
+        .with(ctx.config.cors.middleware())
+}
+
+/// Serves the API until shutdown, shutting everything else down if it stops on its own.
+pub async fn serve(ctx: Ctx, keys: Keys) {
+    if let Err(e) = poem::Server::new(TcpListener::bind(format!(
+        "{}:{}",
+        ctx.config.bind, ctx.config.port
+    )))
+    .run_with_graceful_shutdown(
+        routes(&ctx, keys),
+        ctx.shutdown.wait(),
+        Some(std::time::Duration::from_secs(10)),
+    )
+    .await
+    {
+        error!("HTTP server failed.");
+        debug!("Error: {}", e);
+    }
+
+    // the server can also stop on its own, make sure everything else does too
+    ctx.shutdown.trigger();
+}
@@ -0,0 +1,197 @@
+use futures_util::StreamExt;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    bracket::Observation, capture::Capture, connection::TAConnection, context::Ctx, metrics,
+    packets, proto::packet, servers, shutdown::InFlight, BracketUpdates, TAUpdates,
+};
+
+/// Relays the primary TA server, reconnecting whenever the connection drops,
+/// until shutdown. Starts from `con` if given, otherwise connects first.
+pub async fn run(ctx: Ctx, con: Option<TAConnection>) {
+    let mut con = match con {
+        Some(con) => con,
+        None => tokio::select! {
+            con = reconnect(&ctx) => con,
+            _ = ctx.shutdown.wait() => return,
+        },
+    };
+
+    let mut capture = match &ctx.config.capture {
+        Some(path) => match Capture::open(path).await {
+            Ok(capture) => Some(capture),
+            Err(e) => {
+                warn!("Failed to open capture {}, not capturing.", path.display());
+                debug!("Error: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    for server in ctx.config.servers.clone() {
+        servers::spawn(&ctx, server).await;
+    }
+    let packets = processor(&ctx);
+
+    loop {
+        metrics::TA_CONNECTED.set(1);
+        ctx.health.set_ta_live(true);
+        let shutting_down = tokio::select! {
+            _ = receive(&ctx, &mut con, &mut capture, &packets) => false,
+            _ = ctx.shutdown.wait() => true,
+        };
+        metrics::TA_CONNECTED.set(0);
+        ctx.health.set_ta_live(false);
+        ctx.health.set_server_name(None);
+
+        if shutting_down {
+            info!("Closing connection to server...");
+            con.close().await;
+            break;
+        }
+
+        // not ready again until the new Connect response arrives
+        ctx.ta_state.write().await.server_settings = None;
+
+        warn!("Lost connection to server, reconnecting...");
+        con = tokio::select! {
+            con = reconnect(&ctx) => con,
+            _ = ctx.shutdown.wait() => break,
+        };
+        metrics::TA_RECONNECTS.inc();
+    }
+
+    if let Some(capture) = capture {
+        if let Err(e) = capture.close().await {
+            warn!("Failed to flush capture.");
+            debug!("Error: {}", e);
+        }
+    }
+    servers::closed(&ctx).await;
+    ctx.shutdown
+        .flushed(std::time::Duration::from_secs(5))
+        .await;
+}
+
+/// Routes packets from the TA server until the connection ends.
+async fn receive(
+    ctx: &Ctx,
+    ta_con: &mut TAConnection,
+    capture: &mut Option<Capture>,
+    packets: &mpsc::UnboundedSender<(packet::Packet, InFlight)>,
+) {
+    while let Some(msg) = ta_con.next().await {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("Error receiving message: {}", e);
+                continue;
+            }
+        };
+        if let Some(capture) = capture {
+            if let Err(e) = capture.write(&msg).await {
+                warn!("Failed to capture packet.");
+                debug!("Error: {}", e);
+            }
+        }
+        // the processor outlives every receive loop
+        let _ = packets.send((msg, ctx.shutdown.in_flight()));
+    }
+}
+
+/// Spawns the task that processes packets one at a time, in the order they were
+/// received. It finishes the packets already received if the receive loop is
+/// cancelled by shutdown.
+fn processor(ctx: &Ctx) -> mpsc::UnboundedSender<(packet::Packet, InFlight)> {
+    let (packets, mut queue) = mpsc::unbounded_channel::<(packet::Packet, InFlight)>();
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        while let Some((msg, _in_flight)) = queue.recv().await {
+            process(&ctx, msg).await;
+        }
+    });
+    packets
+}
+
+/// Routes a packet from the primary server and tells subscribers about it.
+pub async fn process(ctx: &Ctx, msg: packet::Packet) {
+    ctx.health.packet_received();
+    metrics::PACKETS_RECEIVED
+        .with_label_values(&[metrics::packet_type(&msg)])
+        .inc();
+
+    let mut state = ctx.ta_state.write().await;
+    let timer = metrics::PACKET_PROCESSING_SECONDS
+        .with_label_values(&[&state.server.uri])
+        .start_timer();
+    let reply = match packets::route_packet(ctx, &mut state, msg.clone()).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            warn!("Error routing packet. {:#?}", msg);
+            debug!("Error: {}", e);
+            None
+        }
+    };
+    timer.observe_duration();
+    ctx.health.set_server_name(
+        state
+            .server_settings
+            .as_ref()
+            .map(|s| s.server_name.as_str()),
+    );
+    // sent while the state is still locked, so passthrough clients joining now
+    // either see the packet in their state or get it streamed, never both
+    if matches!(
+        msg.packet,
+        Some(packet::packet::Packet::Event(_) | packet::packet::Packet::Push(_))
+    ) {
+        ctx.ta_packets.send(msg.clone());
+    }
+    metrics::observe_state(&state);
+    let observation = Observation::of(&state, &msg);
+    let server = state.server.clone();
+    drop(state);
+
+    if let Some(reply) = reply {
+        if let Err(e) = servers::send_as_tx(ctx, &server, reply).await {
+            warn!("Failed to send packet to server.");
+            debug!("Error: {}", e);
+        }
+    }
+
+    if let Some(observation) = observation {
+        let mut bracket = ctx.bracket_state.write().await;
+        if bracket.observe(observation) {
+            if let Err(e) = bracket.save(&ctx.config).await {
+                warn!("Failed to save bracket.");
+                debug!("Error: {}", e);
+            }
+            ctx.bracket_updates.send(BracketUpdates::NewBracket);
+        }
+    }
+
+    // a replay's or simulation's hosts aren't ours to connect to
+    if ctx.config.discover_servers && ctx.config.is_live() {
+        servers::follow_hosts(ctx, &msg).await;
+    }
+
+    ctx.outbound.confirm(&msg);
+    if !packets::is_realtime_score(&msg) {
+        ctx.ta_updates.send(TAUpdates::NewState);
+    }
+}
+
+/// Connects to the TA server again after a second, then backing off up to 30 seconds.
+async fn reconnect(ctx: &Ctx) -> TAConnection {
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    let con = TAConnection::connect_retrying(
+        &ctx.config.ta_uri,
+        &ctx.config.rx_name,
+        &ctx.config.password,
+    )
+    .await;
+    info!("Reconnected to server.");
+    con
+}
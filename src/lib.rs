@@ -0,0 +1,95 @@
+#![allow(clippy::option_map_unit_fn, clippy::module_inception)]
+#![forbid(clippy::unwrap_used)]
+
+//! The TA relay as a library, for embedding it in another tool or building a
+//! different schema on [`TAState`].
+
+pub mod association;
+pub mod auth;
+pub mod bracket;
+pub mod capture;
+pub mod commands;
+pub mod config;
+pub mod connection;
+pub mod context;
+pub mod feed;
+pub mod gql;
+pub mod health;
+pub mod http;
+pub mod ingest;
+pub mod limits;
+pub mod metrics;
+pub mod outbound;
+pub mod packets;
+pub mod passthrough;
+pub mod playback;
+pub mod pool;
+pub mod recorder;
+pub mod relay;
+pub mod replay;
+pub mod rest;
+pub mod roster;
+pub mod servers;
+pub mod shutdown;
+pub mod simulator;
+pub mod snapshot;
+pub mod structs;
+pub mod supervisor;
+
+pub use connection::TAConnection;
+pub use context::{AppContext, Ctx};
+pub use gql::{schema_builder, TarsSchema};
+pub use packets::TAState;
+
+#[allow(non_snake_case)]
+pub mod proto {
+    pub mod discord {
+        include!(concat!(env!("OUT_DIR"), "/proto.discord.rs"));
+    }
+
+    pub mod models {
+        include!(concat!(env!("OUT_DIR"), "/proto.models.rs"));
+    }
+
+    pub mod packet {
+        include!(concat!(env!("OUT_DIR"), "/proto.packet.rs"));
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub enum TAUpdates {
+    /// Something other than a realtime score changed on a server, or in the roster.
+    NewState,
+
+    #[default]
+    None,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub enum PoolUpdates {
+    NewPools,
+    NewPickBan(uuid::Uuid),
+
+    #[default]
+    None,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub enum BracketUpdates {
+    NewBracket,
+
+    #[default]
+    None,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub enum OverUpdates {
+    NewPage,
+
+    #[default]
+    None,
+}
+
+pub fn parse_uuid(uuid: &str) -> uuid::Uuid {
+    uuid::Uuid::parse_str(uuid).expect("Failed to parse UUID")
+}
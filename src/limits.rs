@@ -0,0 +1,241 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use async_graphql::{
+    extensions::{
+        Extension, ExtensionContext, ExtensionFactory, NextPrepareRequest, NextSubscribe,
+        NextValidation,
+    },
+    futures_util::stream::BoxStream,
+    ErrorExtensions, Pos, Request, Response, ServerError, ServerResult, ValidationResult,
+};
+use futures_util::StreamExt;
+use tokio::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{auth::ClientId, config};
+
+/// Rejects queries that are nested too deep or are too complex, and caps the
+/// number of subscriptions a websocket connection can have open at once.
+pub struct QueryLimits(config::Limits);
+
+impl QueryLimits {
+    pub fn new(limits: config::Limits) -> Self {
+        Self(limits)
+    }
+}
+
+impl ExtensionFactory for QueryLimits {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(QueryLimitsExtension(self.0.clone()))
+    }
+}
+
+struct QueryLimitsExtension(config::Limits);
+
+/// The number of open subscriptions on a websocket connection.
+#[derive(Default, Clone)]
+pub struct SubscriptionCount(Arc<AtomicUsize>);
+
+/// Gives back the subscription's slot once it's dropped.
+struct SubscriptionSlot(SubscriptionCount);
+
+impl Drop for SubscriptionSlot {
+    fn drop(&mut self) {
+        self.0 .0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn limit_error(message: String, code: &'static str, limit: usize, actual: usize) -> ServerError {
+    async_graphql::Error::new(message)
+        .extend_with(|_, e| {
+            e.set("code", code);
+            e.set("limit", limit);
+            e.set("actual", actual);
+        })
+        .into_server_error(Pos::default())
+}
+
+#[async_trait::async_trait]
+impl Extension for QueryLimitsExtension {
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        let result = next.run(ctx).await?;
+
+        let mut errors = vec![];
+        if self.0.max_depth > 0 && result.depth > self.0.max_depth {
+            errors.push(limit_error(
+                format!(
+                    "Query is nested too deep ({} > {}).",
+                    result.depth, self.0.max_depth
+                ),
+                "DEPTH_LIMIT_EXCEEDED",
+                self.0.max_depth,
+                result.depth,
+            ));
+        }
+        if self.0.max_complexity > 0 && result.complexity > self.0.max_complexity {
+            errors.push(limit_error(
+                format!(
+                    "Query is too complex ({} > {}).",
+                    result.complexity, self.0.max_complexity
+                ),
+                "COMPLEXITY_LIMIT_EXCEEDED",
+                self.0.max_complexity,
+                result.complexity,
+            ));
+        }
+
+        match errors.is_empty() {
+            true => Ok(result),
+            false => Err(errors),
+        }
+    }
+
+    fn subscribe<'s>(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        stream: BoxStream<'s, Response>,
+        next: NextSubscribe<'_>,
+    ) -> BoxStream<'s, Response> {
+        let max = self.0.max_subscriptions;
+        let count = match ctx.data_opt::<SubscriptionCount>() {
+            Some(count) if max > 0 => count.clone(),
+            _ => return next.run(ctx, stream),
+        };
+
+        let open = count.0.fetch_add(1, Ordering::SeqCst) + 1;
+        let slot = SubscriptionSlot(count);
+        if open > max {
+            return futures_util::stream::once(async move {
+                drop(slot);
+                Response::from_errors(vec![limit_error(
+                    format!(
+                        "Too many subscriptions on this connection ({} > {}).",
+                        open, max
+                    ),
+                    "SUBSCRIPTION_LIMIT_EXCEEDED",
+                    max,
+                    open,
+                )])
+            })
+            .boxed();
+        }
+
+        next.run(ctx, stream)
+            .map(move |response| {
+                let _slot = &slot;
+                response
+            })
+            .boxed()
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// A token bucket per client, refilled at `requests_per_minute`.
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes `cost` requests from the client's bucket, or returns how long to wait
+    /// until they'd be allowed.
+    pub async fn check(&self, client: &str, cost: usize) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = self.per_minute as f64;
+        let per_sec = capacity / 60.0;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().await;
+        // forget clients whose buckets have filled back up
+        if buckets.len() > 1024 {
+            buckets.retain(|_, b| (now - b.updated).as_secs_f64() * per_sec + b.tokens < capacity);
+        }
+
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.tokens =
+            (bucket.tokens + (now - bucket.updated).as_secs_f64() * per_sec).min(capacity);
+        bucket.updated = now;
+
+        let cost = cost as f64;
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (cost.min(capacity) - bucket.tokens) / per_sec,
+            ))
+        }
+    }
+}
+
+pub fn rate_limited(retry_after: Duration) -> ServerError {
+    let secs = retry_after.as_secs().max(1);
+    async_graphql::Error::new(format!("Rate limited, retry in {} seconds.", secs))
+        .extend_with(|_, e| {
+            e.set("code", "RATE_LIMITED");
+            e.set("retryAfter", secs);
+        })
+        .into_server_error(Pos::default())
+}
+
+/// Rate limits every operation sent over a graphql-ws connection, which the HTTP
+/// rate limit only sees once, when the connection is opened. Only applies to
+/// requests whose data has the [`ClientId`] that `connection_init` stores.
+pub struct WebSocketRateLimit(Arc<RateLimiter>);
+
+impl WebSocketRateLimit {
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self(limiter)
+    }
+}
+
+impl ExtensionFactory for WebSocketRateLimit {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(WebSocketRateLimitExtension(self.0.clone()))
+    }
+}
+
+struct WebSocketRateLimitExtension(Arc<RateLimiter>);
+
+#[async_trait::async_trait]
+impl Extension for WebSocketRateLimitExtension {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        if let Some(client) = ctx.data_opt::<ClientId>() {
+            self.0.check(&client.0, 1).await.map_err(rate_limited)?;
+        }
+        next.run(ctx, request).await
+    }
+}
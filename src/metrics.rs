@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use async_graphql::{
+    extensions::{Extension, ExtensionContext, ExtensionFactory, NextRequest, NextSubscribe},
+    futures_util::stream::BoxStream,
+    Response,
+};
+use futures_util::StreamExt;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, TextEncoder,
+};
+
+use crate::{packets::TAState, proto::packet};
+
+lazy_static::lazy_static! {
+    pub static ref PACKETS_RECEIVED: IntCounterVec = register_int_counter_vec!(
+        "tars_packets_received_total",
+        "Packets received from the TA server, by type",
+        &["type"]
+    )
+    .expect("metric can be registered");
+    pub static ref PACKET_PROCESSING_SECONDS: HistogramVec = register_histogram_vec!(
+        "tars_packet_processing_seconds",
+        "Time taken to route a packet into the relay state, by server uri",
+        &["server"]
+    )
+    .expect("metric can be registered");
+    pub static ref TA_CONNECTED: IntGauge = register_int_gauge!(
+        "tars_ta_connected",
+        "Whether the relay is connected to the TA server"
+    )
+    .expect("metric can be registered");
+    pub static ref TA_RECONNECTS: IntCounter = register_int_counter!(
+        "tars_ta_reconnects_total",
+        "Times the relay reconnected to the TA server"
+    )
+    .expect("metric can be registered");
+    pub static ref PLAYERS: IntGaugeVec = register_int_gauge_vec!(
+        "tars_players",
+        "Players connected to each TA server, by server uri",
+        &["server"]
+    )
+    .expect("metric can be registered");
+    pub static ref COORDINATORS: IntGaugeVec = register_int_gauge_vec!(
+        "tars_coordinators",
+        "Coordinators connected to each TA server, by server uri",
+        &["server"]
+    )
+    .expect("metric can be registered");
+    pub static ref MATCHES: IntGaugeVec = register_int_gauge_vec!(
+        "tars_matches",
+        "Matches on each TA server, by server uri",
+        &["server"]
+    )
+    .expect("metric can be registered");
+    pub static ref GRAPHQL_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "tars_graphql_requests_total",
+        "GraphQL queries and mutations, by result",
+        &["result"]
+    )
+    .expect("metric can be registered");
+    pub static ref GRAPHQL_REQUEST_SECONDS: HistogramVec = register_histogram_vec!(
+        "tars_graphql_request_seconds",
+        "Time taken to answer GraphQL queries and mutations, by result",
+        &["result"]
+    )
+    .expect("metric can be registered");
+    pub static ref ACTIVE_SUBSCRIPTIONS: IntGauge = register_int_gauge!(
+        "tars_graphql_active_subscriptions",
+        "GraphQL subscriptions currently open"
+    )
+    .expect("metric can be registered");
+}
+
+/// The label a packet is counted under. Anything `route_packet` ignores is "unhandled".
+pub fn packet_type(packet: &packet::Packet) -> &'static str {
+    match packet.packet {
+        Some(packet::packet::Packet::Event(_)) => "event",
+        Some(packet::packet::Packet::Response(_)) => "response",
+        Some(packet::packet::Packet::Push(_)) => "push",
+        _ => "unhandled",
+    }
+}
+
+pub fn observe_state(state: &TAState) {
+    let server = [state.server.uri.as_str()];
+    PLAYERS
+        .with_label_values(&server)
+        .set(state.players.len() as i64);
+    COORDINATORS
+        .with_label_values(&server)
+        .set(state.coordinators.len() as i64);
+    MATCHES
+        .with_label_values(&server)
+        .set(state.matches.len() as i64);
+}
+
+/// Drops the metrics of a server that is no longer relayed.
+pub fn forget_server(uri: &str) {
+    for gauge in [&*PLAYERS, &*COORDINATORS, &*MATCHES] {
+        let _ = gauge.remove_label_values(&[uri]);
+    }
+    let _ = PACKET_PROCESSING_SECONDS.remove_label_values(&[uri]);
+}
+
+/// All metrics in the Prometheus text format.
+pub fn render() -> anyhow::Result<String> {
+    let mut buf = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buf)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+/// Counts and times GraphQL requests, and tracks open subscriptions.
+pub struct GraphQLMetrics;
+
+impl ExtensionFactory for GraphQLMetrics {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(GraphQLMetricsExtension)
+    }
+}
+
+struct GraphQLMetricsExtension;
+
+struct SubscriptionGuard;
+
+impl Drop for SubscriptionGuard {
+    fn drop(&mut self) {
+        ACTIVE_SUBSCRIPTIONS.dec();
+    }
+}
+
+#[async_trait::async_trait]
+impl Extension for GraphQLMetricsExtension {
+    async fn request(&self, ctx: &ExtensionContext<'_>, next: NextRequest<'_>) -> Response {
+        let timer = std::time::Instant::now();
+        let response = next.run(ctx).await;
+        let result = match response.is_ok() {
+            true => "ok",
+            false => "error",
+        };
+        GRAPHQL_REQUESTS.with_label_values(&[result]).inc();
+        GRAPHQL_REQUEST_SECONDS
+            .with_label_values(&[result])
+            .observe(timer.elapsed().as_secs_f64());
+        response
+    }
+
+    fn subscribe<'s>(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        stream: BoxStream<'s, Response>,
+        next: NextSubscribe<'_>,
+    ) -> BoxStream<'s, Response> {
+        ACTIVE_SUBSCRIPTIONS.inc();
+        let guard = SubscriptionGuard;
+        next.run(ctx, stream)
+            .map(move |response| {
+                let _guard = &guard;
+                response
+            })
+            .boxed()
+    }
+}
@@ -0,0 +1,354 @@
+use futures_util::StreamExt;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::ServerConfig,
+    connection::TAConnection,
+    context::Ctx,
+    proto::{
+        models,
+        packet::{self, event},
+    },
+};
+
+/// How long [`Outbound::send`] waits for its packet to be sent.
+const SEND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// How long [`Outbound::send_confirmed`] waits for TA to announce the change.
+const CONFIRM_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+struct Outgoing {
+    packet: packet::Packet,
+    sent: oneshot::Sender<anyhow::Result<()>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchChange {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl MatchChange {
+    /// The event announcing this change to `r#match`.
+    fn event(self, r#match: models::Match) -> packet::Packet {
+        let r#match = Some(r#match);
+        let changed_object = match self {
+            Self::Created => {
+                event::ChangedObject::MatchCreatedEvent(event::MatchCreatedEvent { r#match })
+            }
+            Self::Updated => {
+                event::ChangedObject::MatchUpdatedEvent(event::MatchUpdatedEvent { r#match })
+            }
+            Self::Deleted => {
+                event::ChangedObject::MatchDeletedEvent(event::MatchDeletedEvent { r#match })
+            }
+        };
+        packet::Packet {
+            id: uuid::Uuid::new_v4().to_string(),
+            from: "".to_string(),
+            packet: Some(packet::packet::Packet::Event(packet::Event {
+                changed_object: Some(changed_object),
+            })),
+        }
+    }
+
+    /// The change a packet announces, and the match it was made to.
+    fn of(packet: &packet::Packet) -> Option<(Self, &models::Match)> {
+        let Some(packet::packet::Packet::Event(packet::Event {
+            changed_object: Some(changed_object),
+        })) = &packet.packet
+        else {
+            return None;
+        };
+        match changed_object {
+            event::ChangedObject::MatchCreatedEvent(event::MatchCreatedEvent {
+                r#match: Some(m),
+            }) => Some((Self::Created, m)),
+            event::ChangedObject::MatchUpdatedEvent(event::MatchUpdatedEvent {
+                r#match: Some(m),
+            }) => Some((Self::Updated, m)),
+            event::ChangedObject::MatchDeletedEvent(event::MatchDeletedEvent {
+                r#match: Some(m),
+            }) => Some((Self::Deleted, m)),
+            _ => None,
+        }
+    }
+}
+
+/// A part of a match that an update can change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MatchField {
+    Users,
+    Leader,
+    Level,
+}
+
+impl MatchField {
+    const ALL: [Self; 3] = [Self::Users, Self::Leader, Self::Level];
+
+    /// Whether two versions of a match agree on this field. Users are compared
+    /// in any order, and levels only by what the relay sets, as TA fills in the rest.
+    fn same(self, a: &models::Match, b: &models::Match) -> bool {
+        match self {
+            Self::Users => {
+                let users = |m: &models::Match| {
+                    let mut users = m.associated_users.clone();
+                    users.sort();
+                    users.dedup();
+                    users
+                };
+                users(a) == users(b)
+            }
+            Self::Leader => a.leader == b.leader,
+            Self::Level => {
+                a.selected_level.as_ref().map(|l| &l.level_id)
+                    == b.selected_level.as_ref().map(|l| &l.level_id)
+                    && a.selected_characteristic
+                        .as_ref()
+                        .map(|c| &c.serialized_name)
+                        == b.selected_characteristic
+                            .as_ref()
+                            .map(|c| &c.serialized_name)
+                    && a.selected_difficulty == b.selected_difficulty
+            }
+        }
+    }
+}
+
+/// A match change sent to TA, waiting for it to come back on the RX connection.
+struct Expected {
+    change: MatchChange,
+    r#match: models::Match,
+    /// What an update changed, which TA's announcement has to agree on.
+    changed: Vec<MatchField>,
+    confirmed: oneshot::Sender<models::Match>,
+}
+
+impl Expected {
+    fn confirmed_by(&self, change: MatchChange, r#match: &models::Match) -> bool {
+        self.change == change
+            && self.r#match.guid == r#match.guid
+            && self.changed.iter().all(|f| f.same(&self.r#match, r#match))
+    }
+}
+
+/// Packets waiting to go out over the relay's TX connection to a server.
+pub struct Outbound {
+    tx: mpsc::Sender<Outgoing>,
+    rx: Mutex<mpsc::Receiver<Outgoing>>,
+    expected: std::sync::Mutex<Vec<Expected>>,
+    /// The TX user's guid, once it has connected.
+    tx_guid: std::sync::Mutex<Option<String>>,
+}
+
+impl Default for Outbound {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Outbound {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel(64);
+        Self {
+            tx,
+            rx: Mutex::new(rx),
+            expected: std::sync::Mutex::new(vec![]),
+            tx_guid: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// The guid of the relay's TX user on the server, if it has connected.
+    pub fn tx_guid(&self) -> Option<String> {
+        self.tx_guid.lock().ok().and_then(|guid| guid.clone())
+    }
+
+    /// Sends `packet` to the server as the TX user, once [`serve`] has.
+    pub async fn send(&self, packet: packet::Packet) -> anyhow::Result<()> {
+        let (sent, result) = oneshot::channel();
+        self.tx
+            .send(Outgoing { packet, sent })
+            .await
+            .map_err(|_| anyhow::anyhow!("The relay has stopped sending packets."))?;
+        match tokio::time::timeout(SEND_TIMEOUT, result).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(anyhow::anyhow!("The relay has stopped sending packets.")),
+            Err(_) => Err(anyhow::anyhow!("Timed out sending packet.")),
+        }
+    }
+
+    /// Tells TA about a `change` to `r#match`, then waits for TA to announce it,
+    /// returning the match as announced.
+    pub async fn send_confirmed(
+        &self,
+        change: MatchChange,
+        r#match: models::Match,
+    ) -> anyhow::Result<models::Match> {
+        self.send_expecting(change, r#match, vec![]).await
+    }
+
+    /// Tells TA that `before` is now `after`, then waits for TA to announce an
+    /// update that agrees on whatever changed, returning the match as announced.
+    pub async fn send_update(
+        &self,
+        before: &models::Match,
+        after: models::Match,
+    ) -> anyhow::Result<models::Match> {
+        let changed = MatchField::ALL
+            .into_iter()
+            .filter(|f| !f.same(before, &after))
+            .collect();
+        self.send_expecting(MatchChange::Updated, after, changed)
+            .await
+    }
+
+    async fn send_expecting(
+        &self,
+        change: MatchChange,
+        r#match: models::Match,
+        changed: Vec<MatchField>,
+    ) -> anyhow::Result<models::Match> {
+        let guid = r#match.guid.clone();
+        let packet = change.event(r#match.clone());
+        let (confirmed, result) = oneshot::channel();
+        if let Ok(mut expected) = self.expected.lock() {
+            expected.push(Expected {
+                change,
+                r#match,
+                changed,
+                confirmed,
+            });
+        }
+        let result = match self.send(packet).await {
+            Ok(()) => match tokio::time::timeout(CONFIRM_TIMEOUT, result).await {
+                Ok(Ok(r#match)) => Ok(r#match),
+                Ok(Err(_)) => Err(anyhow::anyhow!("The relay has stopped receiving packets.")),
+                Err(_) => Err(anyhow::anyhow!(
+                    "Timed out waiting for TA to confirm the change to match {}.",
+                    guid
+                )),
+            },
+            Err(e) => Err(e),
+        };
+        // forget this change if it was never confirmed
+        if let Ok(mut expected) = self.expected.lock() {
+            expected.retain(|e| !e.confirmed.is_closed());
+        }
+        result
+    }
+
+    /// Confirms the match changes waiting on `packet`, if it announces any.
+    pub fn confirm(&self, packet: &packet::Packet) {
+        let Some((change, r#match)) = MatchChange::of(packet) else {
+            return;
+        };
+        let Ok(mut expected) = self.expected.lock() else {
+            return;
+        };
+        for e in std::mem::take(&mut *expected) {
+            // an update only counts if it has the changes sent, not just someone else's
+            match e.confirmed_by(change, r#match) {
+                true => {
+                    let _ = e.confirmed.send(r#match.clone());
+                }
+                false => expected.push(e),
+            }
+        }
+    }
+}
+
+/// Sends queued packets over the primary server's TX connection until shutdown.
+pub async fn run(ctx: Ctx) {
+    let server = ctx.config.primary_server();
+    serve(&ctx, &ctx.outbound, &server, ctx.shutdown.wait()).await;
+}
+
+/// Sends `outbound`'s queued packets over a TX connection to `server`, connecting
+/// when the first one arrives and again whenever the connection drops, until `stop`.
+pub(crate) async fn serve(
+    ctx: &Ctx,
+    outbound: &Outbound,
+    server: &ServerConfig,
+    stop: impl std::future::Future<Output = ()>,
+) {
+    let mut rx = outbound.rx.lock().await;
+    let mut con: Option<TAConnection> = None;
+    tokio::pin!(stop);
+
+    loop {
+        tokio::select! {
+            outgoing = rx.recv() => {
+                let Some(outgoing) = outgoing else {
+                    break;
+                };
+                let result = match ctx.config.is_live() {
+                    true => send(ctx, outbound, server, &mut con, outgoing.packet).await,
+                    false => Err(anyhow::anyhow!("The relay isn't connected to a TA server.")),
+                };
+                let _ = outgoing.sent.send(result);
+            }
+            // TA sends the TX user the same updates as the RX user, which are only read
+            // so the connection doesn't back up
+            incoming = next(&mut con) => {
+                if !matches!(incoming, Some(Ok(_))) {
+                    debug!("TX connection to {} closed.", server.uri);
+                    con = None;
+                }
+            }
+            _ = &mut stop => break,
+        }
+    }
+
+    if let Some(con) = con {
+        con.close().await;
+    }
+}
+
+async fn next(con: &mut Option<TAConnection>) -> Option<anyhow::Result<packet::Packet>> {
+    match con {
+        Some(con) => con.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sends `packet`, reconnecting once if the connection turns out to be gone.
+async fn send(
+    ctx: &Ctx,
+    outbound: &Outbound,
+    server: &ServerConfig,
+    con: &mut Option<TAConnection>,
+    packet: packet::Packet,
+) -> anyhow::Result<()> {
+    if let Err(e) = try_send(ctx, outbound, server, con, packet.clone()).await {
+        warn!("Failed to send packet to {}, reconnecting...", server.uri);
+        debug!("Error: {}", e);
+        *con = None;
+        try_send(ctx, outbound, server, con, packet).await?;
+    }
+    Ok(())
+}
+
+/// Sends `packet`, connecting first if needed.
+async fn try_send(
+    ctx: &Ctx,
+    outbound: &Outbound,
+    server: &ServerConfig,
+    con: &mut Option<TAConnection>,
+    mut packet: packet::Packet,
+) -> anyhow::Result<()> {
+    let tx = match con {
+        Some(tx) => tx,
+        None => {
+            let tx =
+                TAConnection::connect(&server.uri, &ctx.config.tx_name, &server.password).await?;
+            info!("Connected to {} as {}.", server.uri, ctx.config.tx_name);
+            if let Ok(mut guid) = outbound.tx_guid.lock() {
+                *guid = Some(tx.ws_user.guid.clone());
+            }
+            con.insert(tx)
+        }
+    };
+    packet.from = tx.ws_user.guid.clone();
+    tx.send(packet).await
+}
@@ -4,12 +4,15 @@ use tracing::{debug, info, warn};
 use uuid::Uuid;
 
 use crate::{
-    connection::TAConnection,
-    get_ws_uri,
+    association,
+    config,
+    context::AppContext,
+    playback::RtsUpdate,
     proto::{
         models,
         packet::{self, event},
     }, // TA_CON,
+    servers,
 };
 
 #[derive(Debug, Default, Clone)]
@@ -20,6 +23,10 @@ pub struct TAState {
     pub matches: Vec<models::Match>,
     pub servers: Vec<models::CoreServer>,
     pub rts: HashMap<String, models::RealtimeScore>, // perhaps
+    /// Set by the `Connect` response, `None` until the relay is connected.
+    pub server_settings: Option<models::ServerSettings>,
+    /// The TA server this state mirrors.
+    pub server: config::ServerConfig,
 }
 
 impl TAState {
@@ -27,7 +34,54 @@ impl TAState {
         Self::default()
     }
 
-    pub async fn process_event(&mut self, event: packet::Event) -> anyhow::Result<()> {
+    pub fn for_server(server: config::ServerConfig) -> Self {
+        Self {
+            server,
+            ..Self::default()
+        }
+    }
+
+    /// The server's name, or its uri until the `Connect` response arrives.
+    pub fn server_name(&self) -> String {
+        match &self.server_settings {
+            Some(settings) => settings.server_name.clone(),
+            None => self.server.uri.clone(),
+        }
+    }
+
+    /// The server's state as TA would send it in a `Connect` response.
+    pub fn state_proto(&self) -> models::State {
+        models::State {
+            server_settings: self.server_settings.clone(),
+            users: self
+                .server_users
+                .iter()
+                .chain(&self.coordinators)
+                .chain(&self.players)
+                .cloned()
+                .collect(),
+            matches: self.matches.clone(),
+            known_hosts: self.servers.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether `server` is this server's uri or name.
+    pub fn is_server(&self, server: &str) -> bool {
+        self.server.uri == server
+            || self
+                .server_settings
+                .as_ref()
+                .is_some_and(|s| s.server_name == server)
+    }
+
+    /// Applies an event to the state. Returns a packet to send back to the server
+    /// as the TX user, which the caller sends once the state is unlocked.
+    pub async fn process_event(
+        &mut self,
+        ctx: &AppContext,
+        event: packet::Event,
+    ) -> anyhow::Result<Option<packet::Packet>> {
         if let Some(obj) = event.changed_object {
             match obj {
                 event::ChangedObject::UserAddedEvent(e) => {
@@ -103,47 +157,33 @@ impl TAState {
                     match e.r#match {
                         Some(mut r#match) => {
                             info!("Match created: {}", r#match.guid);
-                            //add the overlay to the match's associated users.
-                            r#match.associated_users.extend(
-                                self.server_users
-                                    .iter()
-                                    .filter(|f| !f.name.contains("TX"))
-                                    .map(|u| u.guid.clone()),
+                            let own_tx = servers::tx_guid(ctx, &self.server.uri).await;
+                            let added = association::relay_users(
+                                &ctx.config.association,
+                                self,
+                                &r#match,
+                                own_tx.as_deref(),
                             );
+                            r#match.associated_users.extend(added.iter().cloned());
 
                             self.matches.push(r#match.clone());
 
-                            let mut con = match TAConnection::connect(get_ws_uri(), "TA-Relay-TX")
-                                .await
-                            {
-                                Ok(con) => con,
-                                Err(e) => {
-                                    warn!("Failed to connect to server (tx). Check your websocket uri.");
-                                    return Err(e);
-                                }
-                            };
-
-                            con
-                                // .write()
-                                // .await
-                                // .as_mut()
-                                // .unwrap()
-                                .send(packet::Packet {
-                                    id: Uuid::new_v4().to_string(),
-                                    from: "".to_string(),
-                                    packet: Some(packet::packet::Packet::Event(packet::Event {
-                                        changed_object: Some(
-                                            event::ChangedObject::MatchUpdatedEvent(
-                                                event::MatchUpdatedEvent {
-                                                    r#match: Some(r#match),
-                                                },
-                                            ),
-                                        ),
-                                    })),
-                                })
-                                .await?;
+                            // a replayed or simulated match isn't on the server to update
+                            if added.is_empty() || !ctx.config.is_live() {
+                                return Ok(None);
+                            }
 
-                            con.close().await;
+                            return Ok(Some(packet::Packet {
+                                id: Uuid::new_v4().to_string(),
+                                from: "".to_string(),
+                                packet: Some(packet::packet::Packet::Event(packet::Event {
+                                    changed_object: Some(event::ChangedObject::MatchUpdatedEvent(
+                                        event::MatchUpdatedEvent {
+                                            r#match: Some(r#match),
+                                        },
+                                    )),
+                                })),
+                            }));
                         }
                         None => {
                             warn!("Received MatchCreatedEvent with no match");
@@ -194,7 +234,7 @@ impl TAState {
                 },
             }
         };
-        Ok(())
+        Ok(None)
     }
 
     pub async fn process_response(&mut self, event: packet::Response) -> anyhow::Result<()> {
@@ -204,10 +244,8 @@ impl TAState {
                     packet::response::Details::Connect(c) => {
                         match c.state {
                             Some(state) => {
-                                info!(
-                                    "Connected to server: {}",
-                                    state.server_settings.unwrap_or_default().server_name
-                                );
+                                let server_settings = state.server_settings.unwrap_or_default();
+                                info!("Connected to server: {}", server_settings.server_name);
                                 // unwrap: there will always be a server user, as to receive a connect response, the server must have a user
                                 let server_users = state
                                     .users
@@ -242,6 +280,11 @@ impl TAState {
                                 self.players = players;
                                 self.matches = matches;
                                 self.servers = servers;
+                                self.server_settings = Some(server_settings);
+                                // scores from before a reconnect or restart, for players who've since left
+                                let players = &self.players;
+                                self.rts
+                                    .retain(|guid, _| players.iter().any(|p| &p.guid == guid));
                             }
                             None => {
                                 warn!("Received Connect response with no state");
@@ -262,7 +305,11 @@ impl TAState {
         Ok(())
     }
 
-    pub async fn process_push(&mut self, push: packet::Push) -> anyhow::Result<()> {
+    pub async fn process_push(
+        &mut self,
+        ctx: &AppContext,
+        push: packet::Push,
+    ) -> anyhow::Result<()> {
         match push.data {
             Some(data) => match data {
                 packet::push::Data::RealtimeScore(s) => {
@@ -273,17 +320,13 @@ impl TAState {
                     let user = self.players.iter().find(|u| u.guid == s.user_guid).ok_or(
                         anyhow::anyhow!("RTS sent for a player that does not exist."),
                     )?;
-                    let _ = tokio::fs::create_dir_all(format!("./data/{}", &user.name)).await;
-                    let _ = tokio::fs::write(
-                        format!(
-                            "./data/{}/{}.dat",
-                            user.name,
-                            chrono::Utc::now().timestamp_millis()
-                        ),
-                        format!("{:#?}", &s),
-                    )
-                    .await;
 
+                    ctx.rts_updates.send(RtsUpdate {
+                        user_guid: s.user_guid.clone(),
+                        user_name: user.name.clone(),
+                        received_at_ms: chrono::Utc::now().timestamp_millis(),
+                        score: s.clone(),
+                    });
                     self.rts.insert(s.user_guid.clone(), s);
                 }
                 packet::push::Data::LeaderboardScore(_) => todo!(),
@@ -292,12 +335,8 @@ impl TAState {
                         "SongFinished sent for a player that does not exist."
                     ))?;
                     info!(
-                        "Received SongFinished for {}, their final score was {:#?}",
-                        player.name,
-                        self.rts
-                            .get(player.guid.as_str())
-                            .ok_or(anyhow::anyhow!("RTS not found for player {}.", player.name))?
-                            .score
+                        "Received SongFinished for {}, their final score was {}",
+                        player.name, s.score
                     );
                 }
             },
@@ -309,17 +348,23 @@ impl TAState {
     }
 }
 
-pub async fn route_packet(state: &mut TAState, packet: packet::Packet) -> anyhow::Result<()> {
+/// Routes a packet into `state`. Returns a packet to send back to the server as
+/// the TX user once `state` is unlocked, see [`crate::servers::send_as_tx`].
+pub async fn route_packet(
+    ctx: &AppContext,
+    state: &mut TAState,
+    packet: packet::Packet,
+) -> anyhow::Result<Option<packet::Packet>> {
     debug!("Received packet: {:?}", packet.packet);
     match packet.packet {
         Some(packet::packet::Packet::Event(p)) => {
-            state.process_event(p).await?;
+            return state.process_event(ctx, p).await;
         }
         Some(packet::packet::Packet::Response(p)) => {
             state.process_response(p).await?;
         }
         Some(packet::packet::Packet::Push(p)) => {
-            state.process_push(p).await?;
+            state.process_push(ctx, p).await?;
         }
         None => {}
         _ => {
@@ -333,7 +378,18 @@ pub async fn route_packet(state: &mut TAState, packet: packet::Packet) -> anyhow
             );
         }
     }
-    Ok(())
+    Ok(None)
+}
+
+/// Whether `packet` is a realtime score push. Those come several times a second
+/// per player and only change scores, which are streamed on their own.
+pub fn is_realtime_score(packet: &packet::Packet) -> bool {
+    matches!(
+        packet.packet,
+        Some(packet::packet::Packet::Push(packet::Push {
+            data: Some(packet::push::Data::RealtimeScore(_)),
+        }))
+    )
 }
 
 #[inline]
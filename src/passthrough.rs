@@ -0,0 +1,120 @@
+use futures_util::{SinkExt, StreamExt};
+use poem::{
+    handler,
+    web::{
+        websocket::{CloseCode, Message, WebSocket, WebSocketStream},
+        Data,
+    },
+    IntoResponse, Response,
+};
+use prost::Message as _;
+
+use crate::{
+    context::Ctx,
+    gql::sink_stream,
+    packets::TAState,
+    proto::packet::{self, request, response},
+};
+
+/// TA's own websocket protocol, for overlays that talk to TA directly. Clients
+/// connect as they would to TA, then get the primary server's events and pushes.
+/// Anything else they send is ignored.
+#[handler]
+pub async fn passthrough_route(ctx: Data<&Ctx>, websocket: WebSocket) -> Response {
+    let ctx = ctx.clone();
+    websocket
+        .on_upgrade(move |stream| serve(ctx, stream))
+        .into_response()
+}
+
+async fn serve(ctx: Ctx, stream: WebSocketStream) {
+    let (mut sink, mut stream) = stream.split();
+    let shutdown = ctx.shutdown.wait();
+    tokio::pin!(shutdown);
+
+    // like TA, nothing is sent until the client asks to connect
+    let (id, connect) = loop {
+        tokio::select! {
+            msg = stream.next() => {
+                let data = match msg {
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => return,
+                    Some(Ok(_)) => continue,
+                };
+                if let Ok(packet::Packet {
+                    id,
+                    packet:
+                        Some(packet::packet::Packet::Request(packet::Request {
+                            r#type: Some(request::Type::Connect(connect)),
+                        })),
+                    ..
+                }) = packet::Packet::decode(data.as_slice())
+                {
+                    break (id, connect);
+                }
+            }
+            _ = &mut shutdown => return,
+        }
+    };
+
+    // subscribed while the state is locked, so no packet is both in the state
+    // and streamed afterwards
+    let (response, updates) = {
+        let state = ctx.ta_state.read().await;
+        (
+            connect_response(&state, id, connect),
+            sink_stream(&ctx.ta_packets),
+        )
+    };
+    let mut updates = Box::pin(updates);
+    if sink
+        .send(Message::Binary(response.encode_to_vec()))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            packet = updates.next() => {
+                let Some(packet) = packet else {
+                    break;
+                };
+                if sink.send(Message::Binary(packet.encode_to_vec())).await.is_err() {
+                    return;
+                }
+            }
+            msg = stream.next() => {
+                if matches!(msg, Some(Ok(Message::Close(_))) | None | Some(Err(_))) {
+                    return;
+                }
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    let _ = sink
+        .send(Message::close_with(CloseCode::Away, "Server shutting down"))
+        .await;
+}
+
+/// The `Connect` response TA would send, with the client among the users.
+fn connect_response(state: &TAState, id: String, connect: request::Connect) -> packet::Packet {
+    let mut ta_state = state.state_proto();
+    ta_state.users.extend(connect.user);
+    packet::Packet {
+        id: uuid::Uuid::new_v4().to_string(),
+        from: uuid::Uuid::nil().to_string(),
+        packet: Some(packet::packet::Packet::Response(packet::Response {
+            r#type: response::ResponseType::Success.into(),
+            responding_to_packet_id: id,
+            details: Some(response::Details::Connect(response::Connect {
+                state: Some(ta_state),
+                server_version: connect.client_version,
+                message: format!("Connected to {} through TARS", state.server_name()),
+                ..Default::default()
+            })),
+        })),
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+
+use futures_util::{Stream, StreamExt};
+use tokio::time::{Duration, Instant};
+
+use crate::proto::models;
+
+/// A realtime score as it arrived from the TA server.
+#[derive(Debug, Clone)]
+pub struct RtsUpdate {
+    pub user_guid: String,
+    pub user_name: String,
+    /// Unix time in milliseconds.
+    pub received_at_ms: i64,
+    pub score: models::RealtimeScore,
+}
+
+/// How far a player's stream lags behind the realtime score feed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StreamDelay {
+    pub delay_ms: i64,
+    /// Unix time in milliseconds at which the player's stream started the song, 0 if unknown.
+    pub sync_start_ms: i64,
+}
+
+impl RtsUpdate {
+    /// The unix time in milliseconds at which this score is visible on the player's stream.
+    pub fn release_at_ms(&self, delay: StreamDelay) -> i64 {
+        let delayed = self.received_at_ms + delay.delay_ms.max(0);
+        match delay.sync_start_ms > 0 {
+            // nothing the player does is on stream before the synced start
+            true => delayed.max(delay.sync_start_ms + delay.delay_ms.max(0)),
+            false => delayed,
+        }
+    }
+}
+
+/// Holds back each update until the player's stream has caught up with it.
+///
+/// `delay_for` is asked for the player's delay when an update arrives, so changes
+/// to a player's delay apply to new scores only.
+pub fn delay_scores<S, F, Fut>(updates: S, delay_for: F) -> impl Stream<Item = RtsUpdate>
+where
+    S: Stream<Item = RtsUpdate> + Send + 'static,
+    F: Fn(String) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = StreamDelay> + Send,
+{
+    async_stream::stream! {
+        let mut updates = Box::pin(updates);
+        let mut queue: VecDeque<(Instant, RtsUpdate)> = VecDeque::new();
+
+        loop {
+            let next_release = queue.front().map(|(at, _)| *at);
+            tokio::select! {
+                update = updates.next() => match update {
+                    Some(update) => {
+                        let delay = delay_for(update.user_guid.clone()).await;
+                        let wait = update.release_at_ms(delay) - chrono::Utc::now().timestamp_millis();
+                        let at = Instant::now() + Duration::from_millis(wait.max(0) as u64);
+                        let index = queue.partition_point(|(a, _)| *a <= at);
+                        queue.insert(index, (at, update));
+                    }
+                    None => break,
+                },
+                _ = tokio::time::sleep_until(next_release.unwrap_or_else(Instant::now)), if next_release.is_some() => {
+                    if let Some((_, update)) = queue.pop_front() {
+                        yield update;
+                    }
+                }
+            }
+        }
+
+        // flush whatever is left once the feed ends
+        while let Some((at, update)) = queue.pop_front() {
+            tokio::time::sleep_until(at).await;
+            yield update;
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+
+use async_graphql::{Enum, InputObject, SimpleObject};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::Config;
+
+const POOLS_FILE: &str = "pools.json";
+const PICK_BANS_FILE: &str = "pick_bans.json";
+
+#[derive(SimpleObject, Serialize, Deserialize, Clone)]
+pub struct MapPool {
+    pub guid: Uuid,
+    pub name: String,
+    pub maps: Vec<PoolMap>,
+}
+
+#[derive(SimpleObject, Serialize, Deserialize, Clone)]
+pub struct PoolMap {
+    /// The slot of the map in the pool, e.g. "NM1" or "TB".
+    pub label: String,
+    pub hash: String,
+    pub difficulty: i32,
+    pub characteristic: String,
+}
+
+#[derive(InputObject, Clone)]
+pub struct InputPoolMap {
+    pub label: String,
+    pub hash: String,
+    pub difficulty: i32,
+    #[graphql(default = "Standard")]
+    pub characteristic: String,
+}
+
+#[derive(Enum, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+pub enum PickBanAction {
+    Pick,
+    Ban,
+}
+
+#[derive(SimpleObject, Serialize, Deserialize, Clone)]
+pub struct PickBanStep {
+    pub action: PickBanAction,
+    pub label: String,
+    /// The player or team that made the pick or ban, if known.
+    pub by: Option<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PickBan {
+    pub pool: Uuid,
+    pub steps: Vec<PickBanStep>,
+}
+
+#[derive(SimpleObject)]
+pub struct GQLPickBan {
+    pub match_guid: Uuid,
+    pub pool: MapPool,
+    pub steps: Vec<PickBanStep>,
+    pub picks: Vec<PoolMap>,
+    pub bans: Vec<PoolMap>,
+    pub remaining: Vec<PoolMap>,
+}
+
+#[derive(Default)]
+pub struct PoolState {
+    pub pools: Vec<MapPool>,
+    pub pick_bans: HashMap<Uuid, PickBan>,
+}
+
+impl InputPoolMap {
+    pub fn into_pool_map(self) -> PoolMap {
+        PoolMap {
+            label: self.label,
+            hash: self.hash.to_uppercase(),
+            difficulty: self.difficulty,
+            characteristic: self.characteristic,
+        }
+    }
+}
+
+impl PoolState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn load(&mut self, config: &Config) -> anyhow::Result<()> {
+        let data = match tokio::fs::read(config.data_path(POOLS_FILE)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        self.pools = serde_json::from_slice(&data)?;
+        info!("Loaded {} map pools", self.pools.len());
+
+        let data = match tokio::fs::read(config.data_path(PICK_BANS_FILE)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        self.pick_bans = serde_json::from_slice(&data)?;
+        info!("Loaded {} pick/bans", self.pick_bans.len());
+        Ok(())
+    }
+
+    /// Writes the pools and every match's pick/ban to the data directory.
+    pub async fn save(&self, config: &Config) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&config.data_dir).await?;
+        tokio::fs::write(
+            config.data_path(POOLS_FILE),
+            serde_json::to_vec_pretty(&self.pools)?,
+        )
+        .await?;
+        tokio::fs::write(
+            config.data_path(PICK_BANS_FILE),
+            serde_json::to_vec_pretty(&self.pick_bans)?,
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub fn get_pool(&self, guid: Uuid) -> anyhow::Result<&MapPool> {
+        self.pools
+            .iter()
+            .find(|p| p.guid == guid)
+            .ok_or(anyhow::anyhow!("No map pool with id {}.", guid))
+    }
+
+    pub fn add_pool(&mut self, name: String, maps: Vec<PoolMap>) -> anyhow::Result<MapPool> {
+        let mut labels = maps.iter().map(|m| m.label.as_str()).collect::<Vec<_>>();
+        labels.sort();
+        if let Some(label) = labels.windows(2).find(|w| w[0] == w[1]).map(|w| w[0]) {
+            return Err(anyhow::anyhow!("Map pool contains label {} twice.", label));
+        }
+
+        let pool = MapPool {
+            guid: Uuid::new_v4(),
+            name,
+            maps,
+        };
+        info!("Map pool created: {}", pool.name);
+        self.pools.push(pool.clone());
+        Ok(pool)
+    }
+
+    pub fn remove_pool(&mut self, guid: Uuid) -> anyhow::Result<()> {
+        self.get_pool(guid)?;
+        self.pools.retain(|p| p.guid != guid);
+        // a pick/ban sequence is meaningless without its pool
+        self.pick_bans.retain(|_, pb| pb.pool != guid);
+        Ok(())
+    }
+
+    pub fn set_match_pool(&mut self, match_guid: Uuid, pool: Uuid) -> anyhow::Result<()> {
+        self.get_pool(pool)?;
+        self.pick_bans.insert(
+            match_guid,
+            PickBan {
+                pool,
+                steps: vec![],
+            },
+        );
+        Ok(())
+    }
+
+    pub fn apply(
+        &mut self,
+        match_guid: Uuid,
+        action: PickBanAction,
+        label: String,
+        by: Option<Uuid>,
+    ) -> anyhow::Result<()> {
+        let pick_ban = self
+            .pick_bans
+            .get(&match_guid)
+            .ok_or(anyhow::anyhow!("Match {} has no map pool.", match_guid))?;
+        let pool = self.get_pool(pick_ban.pool)?;
+
+        if !pool.maps.iter().any(|m| m.label == label) {
+            return Err(anyhow::anyhow!(
+                "Map pool {} has no map {}.",
+                pool.name,
+                label
+            ));
+        }
+        if pick_ban.steps.iter().any(|s| s.label == label) {
+            return Err(anyhow::anyhow!(
+                "Map {} was already picked or banned.",
+                label
+            ));
+        }
+
+        self.pick_bans
+            .get_mut(&match_guid)
+            .map(|pb| pb.steps.push(PickBanStep { action, label, by }));
+        Ok(())
+    }
+
+    pub fn undo(&mut self, match_guid: Uuid) -> anyhow::Result<PickBanStep> {
+        self.pick_bans
+            .get_mut(&match_guid)
+            .ok_or(anyhow::anyhow!("Match {} has no map pool.", match_guid))?
+            .steps
+            .pop()
+            .ok_or(anyhow::anyhow!("Nothing to undo for match {}.", match_guid))
+    }
+
+    pub fn reset(&mut self, match_guid: Uuid) -> anyhow::Result<()> {
+        self.pick_bans
+            .get_mut(&match_guid)
+            .ok_or(anyhow::anyhow!("Match {} has no map pool.", match_guid))?
+            .steps
+            .clear();
+        Ok(())
+    }
+
+    /// Finds the pool slot of a map, preferring the pool assigned to the match.
+    pub fn slot_for(&self, match_guid: Uuid, hash: &str, difficulty: i32) -> Option<PoolMap> {
+        let find = |pool: &MapPool| {
+            pool.maps
+                .iter()
+                .find(|m| m.hash.eq_ignore_ascii_case(hash) && m.difficulty == difficulty)
+                .cloned()
+        };
+
+        match self.pick_bans.get(&match_guid) {
+            Some(pick_ban) => self.get_pool(pick_ban.pool).ok().and_then(find),
+            None => self.pools.iter().find_map(find),
+        }
+    }
+
+    pub fn get_pick_ban_gql(&self, match_guid: Uuid) -> anyhow::Result<Option<GQLPickBan>> {
+        let pick_ban = match self.pick_bans.get(&match_guid) {
+            Some(pick_ban) => pick_ban,
+            None => return Ok(None),
+        };
+        let pool = self.get_pool(pick_ban.pool)?.clone();
+        let by_action = |action: PickBanAction| {
+            pick_ban
+                .steps
+                .iter()
+                .filter(|s| s.action == action)
+                .filter_map(|s| pool.maps.iter().find(|m| m.label == s.label).cloned())
+                .collect::<Vec<_>>()
+        };
+
+        Ok(Some(GQLPickBan {
+            match_guid,
+            picks: by_action(PickBanAction::Pick),
+            bans: by_action(PickBanAction::Ban),
+            remaining: pool
+                .maps
+                .iter()
+                .filter(|m| !pick_ban.steps.iter().any(|s| s.label == m.label))
+                .cloned()
+                .collect(),
+            steps: pick_ban.steps.clone(),
+            pool,
+        }))
+    }
+}
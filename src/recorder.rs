@@ -0,0 +1,48 @@
+use futures_util::{FutureExt, StreamExt};
+use tracing::{debug, warn};
+
+use crate::{context::Ctx, gql::sink_stream, playback::RtsUpdate};
+
+/// Writes every realtime score to `<data dir>/<player name>/<unix ms>.dat`.
+///
+/// Once shutdown starts, it waits for the packets still being routed and writes
+/// whatever scores they produced before returning.
+pub async fn record_scores(ctx: Ctx) {
+    if !ctx.config.features.record_rts {
+        return;
+    }
+    let mut updates = Box::pin(sink_stream(&ctx.rts_updates));
+    let shutdown = ctx.shutdown.wait();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            Some(update) = updates.next() => write_score(&ctx, &update).await,
+            _ = &mut shutdown => break,
+        }
+    }
+
+    ctx.shutdown
+        .flushed(std::time::Duration::from_secs(5))
+        .await;
+    while let Some(Some(update)) = updates.next().now_or_never() {
+        write_score(&ctx, &update).await;
+    }
+}
+
+async fn write_score(ctx: &Ctx, update: &RtsUpdate) {
+    let dir = ctx.config.data_path(&update.user_name);
+    let result = async {
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::write(
+            dir.join(format!("{}.dat", update.received_at_ms)),
+            format!("{:#?}", &update.score),
+        )
+        .await
+    }
+    .await;
+    if let Err(e) = result {
+        warn!("Failed to record score for {}.", update.user_name);
+        debug!("Error: {}", e);
+    }
+}
@@ -0,0 +1,70 @@
+use tracing::{debug, info, warn};
+
+use crate::{
+    auth::Keys, connection::TAConnection, context::Ctx, http, ingest, outbound, recorder, replay,
+    simulator, snapshot, supervisor::supervise, TAUpdates,
+};
+
+/// Runs the relay until shutdown: the TA ingest starting from `con` (or the
+/// replay or simulator, if configured), the TX sender, the roster reloader, the recorders and the HTTP
+/// server, each restarted if it panics.
+pub async fn run(ctx: Ctx, keys: Keys, con: Option<TAConnection>) {
+    if let Err(e) = ctx.pool_state.write().await.load(&ctx.config).await {
+        warn!("Failed to load map pools. Starting without any.");
+        debug!("Error: {}", e);
+    }
+    if let Err(e) = ctx.bracket_state.write().await.load(&ctx.config).await {
+        warn!("Failed to load bracket. Starting without one.");
+        debug!("Error: {}", e);
+    }
+    if ctx.config.is_live() {
+        if let Err(e) = snapshot::restore(&ctx).await {
+            warn!("Failed to restore snapshot. Starting from scratch.");
+            debug!("Error: {}", e);
+        }
+    }
+
+    let mut tasks = tokio::task::JoinSet::new();
+    if ctx.config.replay.is_some() {
+        tasks.spawn(supervise(ctx.clone(), "replay", replay::run));
+    } else if ctx.config.simulator.enabled {
+        tasks.spawn(supervise(ctx.clone(), "simulator", simulator::run));
+    } else {
+        // only the first run gets the connection passed in, restarts connect again
+        let first_con = std::sync::Mutex::new(con);
+        tasks.spawn(supervise(ctx.clone(), "ingest", move |ctx| {
+            let con = first_con.lock().ok().and_then(|mut con| con.take());
+            ingest::run(ctx, con)
+        }));
+    }
+    tasks.spawn(supervise(ctx.clone(), "outbound", outbound::run));
+    tasks.spawn(supervise(ctx.clone(), "roster", reload_roster));
+    tasks.spawn(supervise(ctx.clone(), "recorder", recorder::record_scores));
+    tasks.spawn(supervise(ctx.clone(), "snapshot", snapshot::run));
+    tasks.spawn(supervise(ctx.clone(), "http", move |ctx| {
+        http::serve(ctx, keys.clone())
+    }));
+
+    while tasks.join_next().await.is_some() {}
+    info!("Shut down.");
+}
+
+/// Picks up changes to the roster file every 2 seconds until shutdown.
+async fn reload_roster(ctx: Ctx) {
+    let path = ctx.config.roster_path().to_string_lossy().to_string();
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(2));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {},
+            _ = ctx.shutdown.wait() => return,
+        }
+        match ctx.roster.write().await.reload(&path).await {
+            Ok(true) => ctx.ta_updates.send(TAUpdates::NewState),
+            Ok(false) => {}
+            Err(e) => {
+                warn!("Failed to load roster {}.", path);
+                debug!("Error: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_graphql::SimpleObject;
+use tokio::{sync::Semaphore, time::Instant};
+use tracing::{debug, error, info};
+
+use crate::{capture, config::ReplaySpeed, context::Ctx, ingest};
+
+/// Progress through the capture being replayed, and the steps asked for.
+pub struct Replay {
+    steps: Semaphore,
+    position: AtomicUsize,
+    total: AtomicUsize,
+}
+
+#[derive(SimpleObject)]
+pub struct GQLReplay {
+    /// Packets replayed so far.
+    pub position: usize,
+    pub total: usize,
+    pub stepped: bool,
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Replay {
+    pub fn new() -> Self {
+        Self {
+            steps: Semaphore::new(0),
+            position: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+        }
+    }
+
+    /// Lets a stepped replay go on by `packets` packets.
+    pub fn step(&self, packets: usize) {
+        self.steps.add_permits(packets);
+    }
+
+    pub fn progress_gql(&self, speed: ReplaySpeed) -> GQLReplay {
+        GQLReplay {
+            position: self.position.load(Ordering::SeqCst),
+            total: self.total.load(Ordering::SeqCst),
+            stepped: speed == ReplaySpeed::Stepped,
+        }
+    }
+}
+
+/// Feeds the configured capture through the same path as live packets, at the
+/// configured speed, until it ends or shutdown starts.
+pub async fn run(ctx: Ctx) {
+    let Some(path) = ctx.config.replay.clone() else {
+        return;
+    };
+    let records = match capture::read(&path).await {
+        Ok(records) => records,
+        Err(e) => {
+            error!("Failed to read capture {}.", path.display());
+            debug!("Error: {}", e);
+            return;
+        }
+    };
+    info!(
+        "Replaying {} packets from {}",
+        records.len(),
+        path.display()
+    );
+    ctx.replay.total.store(records.len(), Ordering::SeqCst);
+    ctx.health.set_ta_live(true);
+
+    // a restarted replay picks up where it left off
+    let records = records
+        .into_iter()
+        .skip(ctx.replay.position.load(Ordering::SeqCst))
+        .collect::<Vec<_>>();
+    let started = Instant::now();
+    let first_ms = records
+        .first()
+        .map(|r| r.received_at_ms)
+        .unwrap_or_default();
+    let shutdown = ctx.shutdown.wait();
+    tokio::pin!(shutdown);
+
+    for record in records {
+        let wait = async {
+            match ctx.config.replay_speed {
+                ReplaySpeed::Timed(speed) => {
+                    let offset = (record.received_at_ms - first_ms).max(0) as f64 / speed;
+                    tokio::time::sleep_until(
+                        started + std::time::Duration::from_secs_f64(offset / 1000.0),
+                    )
+                    .await;
+                }
+                ReplaySpeed::Stepped => {
+                    if let Ok(permit) = ctx.replay.steps.acquire().await {
+                        permit.forget();
+                    }
+                }
+            }
+        };
+        tokio::select! {
+            _ = wait => {},
+            _ = &mut shutdown => break,
+        }
+
+        if let Some(packet) = record.packet {
+            ingest::process(&ctx, packet).await;
+        }
+        ctx.replay.position.fetch_add(1, Ordering::SeqCst);
+    }
+
+    if !ctx.shutdown.is_triggered() {
+        info!("Replay finished.");
+    }
+}
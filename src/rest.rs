@@ -0,0 +1,142 @@
+use std::{
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use poem::{
+    get, handler,
+    http::{header, StatusCode},
+    web::{Data, Path, Query},
+    Endpoint, EndpointExt, IntoResponse, Request, Response, Route,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{context::Ctx, http::client_id, limits::RateLimiter, servers};
+
+#[derive(Deserialize)]
+struct ServerQuery {
+    /// Uri or name of the server, the primary server if not given.
+    server: Option<String>,
+}
+
+/// Serves `body` as JSON with an ETag, or just the ETag if the client already
+/// has this version.
+fn json<T: Serialize>(req: &Request, body: &T) -> Response {
+    let body = match serde_json::to_vec(body) {
+        Ok(body) => body,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e.into()),
+    };
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    body.hash(&mut hasher);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+
+    let unchanged = req.header(header::IF_NONE_MATCH).is_some_and(|tags| {
+        tags.split(',')
+            .map(|t| t.trim().trim_start_matches("W/"))
+            .any(|t| t == etag || t == "*")
+    });
+    let resp = Response::builder()
+        .header(header::ETAG, etag)
+        .header(header::CACHE_CONTROL, "no-cache");
+    match unchanged {
+        true => resp.status(StatusCode::NOT_MODIFIED).finish(),
+        false => resp.content_type("application/json").body(body),
+    }
+}
+
+fn error(status: StatusCode, e: anyhow::Error) -> Response {
+    Response::builder()
+        .status(status)
+        .content_type("application/json")
+        .body(serde_json::json!({ "error": e.to_string() }).to_string())
+}
+
+fn not_found(what: &str, id: Uuid) -> Response {
+    error(
+        StatusCode::NOT_FOUND,
+        anyhow::anyhow!("No {} with id {}.", what, id),
+    )
+}
+
+/// Like `Query::state`.
+#[handler]
+async fn state_route(ctx: Data<&Ctx>, req: &Request, Query(q): Query<ServerQuery>) -> Response {
+    let server = q.server.as_deref();
+    if let Err(e) = servers::with_state(&ctx, server, |_| ()).await {
+        return error(StatusCode::NOT_FOUND, e);
+    }
+    match servers::state_gql(&ctx, server).await {
+        Ok(state) => json(req, &state),
+        Err(e) => {
+            warn!("Failed to read state.");
+            debug!("Error: {}", e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, e)
+        }
+    }
+}
+
+/// Like `Query::all_matches`.
+#[handler]
+async fn matches_route(ctx: Data<&Ctx>, req: &Request) -> Response {
+    match servers::all_matches_gql(&ctx).await {
+        Ok(matches) => json(req, &matches),
+        Err(e) => {
+            warn!("Failed to list matches.");
+            debug!("Error: {}", e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, e)
+        }
+    }
+}
+
+/// Like `Query::match_by_id`.
+#[handler]
+async fn match_route(ctx: Data<&Ctx>, req: &Request, Path(id): Path<Uuid>) -> Response {
+    match servers::match_gql(&ctx, id).await {
+        Ok(Some(r#match)) => json(req, &r#match),
+        Ok(None) => not_found("match", id),
+        Err(e) => {
+            warn!("Failed to read match {}.", id);
+            debug!("Error: {}", e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, e)
+        }
+    }
+}
+
+#[handler]
+async fn player_route(ctx: Data<&Ctx>, req: &Request, Path(id): Path<Uuid>) -> Response {
+    match servers::player_gql(&ctx, id).await {
+        Some(player) => json(req, &player),
+        None => not_found("player", id),
+    }
+}
+
+#[handler]
+async fn page_route(ctx: Data<&Ctx>, req: &Request) -> Response {
+    json(req, &*ctx.over_state.read().await)
+}
+
+/// Counts every request against the client's rate limit.
+async fn rate_limit<E: Endpoint>(ep: Arc<E>, req: Request) -> poem::Result<Response> {
+    if let Some(limiter) = req.data::<Arc<RateLimiter>>() {
+        if let Err(retry_after) = limiter.check(&client_id(&req), 1).await {
+            return Ok(Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header("Retry-After", retry_after.as_secs().max(1))
+                .body("Rate limited"));
+        }
+    }
+    ep.call(req).await.map(IntoResponse::into_response)
+}
+
+/// Read-only JSON mirrors of the GraphQL queries, to nest under `/api/v1`.
+pub fn routes() -> impl Endpoint {
+    Route::new()
+        .at("/state", get(state_route))
+        .at("/matches", get(matches_route))
+        .at("/matches/:id", get(match_route))
+        .at("/players/:id", get(player_route))
+        .at("/page", get(page_route))
+        .around(rate_limit)
+}
@@ -0,0 +1,86 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use async_graphql::SimpleObject;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+/// Relay-side information about a player that TA does not know about.
+#[derive(SimpleObject, Serialize, Deserialize, Clone, Default)]
+pub struct Profile {
+    #[serde(default)]
+    pub user_id: String,
+    pub display_name: Option<String>,
+    /// ISO 3166-1 alpha-2 country code, for flags.
+    pub country: Option<String>,
+    pub pronouns: Option<String>,
+    pub seed: Option<i32>,
+    pub avatar: Option<String>,
+    pub twitch: Option<String>,
+    pub twitter: Option<String>,
+    pub youtube: Option<String>,
+    pub discord: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Roster {
+    pub profiles: HashMap<String, Profile>,
+    modified: Option<SystemTime>,
+}
+
+/// Parses a roster. `.json` and `.toml` files are tables keyed by user id,
+/// `.csv` files need a `user_id` column.
+pub fn parse_roster(path: &str, data: &[u8]) -> anyhow::Result<HashMap<String, Profile>> {
+    let mut profiles = if path.ends_with(".csv") {
+        csv::Reader::from_reader(data)
+            .deserialize()
+            .map(|p| p.map(|p: Profile| (p.user_id.clone(), p)))
+            .collect::<Result<HashMap<_, _>, _>>()?
+    } else if path.ends_with(".toml") {
+        toml::from_str::<HashMap<String, Profile>>(std::str::from_utf8(data)?)?
+    } else if path.ends_with(".json") {
+        serde_json::from_slice::<HashMap<String, Profile>>(data)?
+    } else {
+        return Err(anyhow::anyhow!(
+            "Roster {} must be a .json, .toml or .csv file.",
+            path
+        ));
+    };
+
+    profiles.iter_mut().for_each(|(k, p)| p.user_id = k.clone());
+    Ok(profiles)
+}
+
+impl Roster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, user_id: &str) -> Option<&Profile> {
+        self.profiles.get(user_id)
+    }
+
+    /// Reloads the roster if the file changed since the last load.
+    /// Returns whether the roster changed.
+    pub async fn reload(&mut self, path: &str) -> anyhow::Result<bool> {
+        let modified = match tokio::fs::metadata(path).await {
+            Ok(meta) => Some(meta.modified()?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        if modified == self.modified {
+            return Ok(false);
+        }
+
+        // recorded before parsing, so a broken file is only reported once
+        self.modified = modified;
+        self.profiles = match modified {
+            Some(_) => parse_roster(path, &tokio::fs::read(path).await?)?,
+            None => {
+                debug!("No roster at {}", path);
+                HashMap::new()
+            }
+        };
+        info!("Loaded roster with {} players", self.profiles.len());
+        Ok(true)
+    }
+}
@@ -0,0 +1,464 @@
+use std::sync::Arc;
+
+use futures_util::StreamExt;
+use tokio::{
+    sync::{mpsc, RwLock},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::ServerConfig,
+    connection::TAConnection,
+    context::{AppContext, Ctx},
+    metrics,
+    outbound::{self, Outbound},
+    packets::{self, TAState},
+    proto::{
+        models,
+        packet::{self, event, Packet},
+    },
+    shutdown::{InFlight, Shutdown},
+    structs::{GQLServer, GQLTAState, Match, User},
+    TAUpdates,
+};
+
+/// A relayed server other than the primary one.
+pub(crate) struct Relay {
+    /// Stops just this server, when TA forgets a host it was discovered from.
+    stop: Shutdown,
+    /// The server's TX connection, kept open like the primary server's.
+    outbound: Arc<Outbound>,
+    discovered: bool,
+    task: JoinHandle<()>,
+}
+
+/// Starts relaying a TA server, unless it's the primary one or already relayed.
+pub async fn spawn(ctx: &Ctx, server: ServerConfig) {
+    start(ctx, server, false).await;
+}
+
+async fn start(ctx: &Ctx, server: ServerConfig, discovered: bool) {
+    if same_server(&server.uri, &ctx.config.ta_uri)
+        || ctx.server_states.read().await.contains_key(&server.uri)
+    {
+        return;
+    }
+    let mut states = ctx.server_states.write().await;
+    if states.contains_key(&server.uri) {
+        return;
+    }
+    info!("Relaying server {}", server.uri);
+    states.insert(
+        server.uri.clone(),
+        Arc::new(RwLock::new(TAState::for_server(server.clone()))),
+    );
+    drop(states);
+
+    let stop = Shutdown::new();
+    let outbound = Arc::new(Outbound::new());
+    let task = tokio::spawn(run(
+        ctx.clone(),
+        server.clone(),
+        stop.clone(),
+        outbound.clone(),
+    ));
+    ctx.relays.lock().await.insert(
+        server.uri,
+        Relay {
+            stop,
+            outbound,
+            discovered,
+            task,
+        },
+    );
+}
+
+/// Stops relaying a discovered server and forgets its state.
+async fn stop(ctx: &Ctx, uri: &str) {
+    let mut relays = ctx.relays.lock().await;
+    if !relays.get(uri).is_some_and(|r| r.discovered) {
+        return;
+    }
+    let Some(relay) = relays.remove(uri) else {
+        return;
+    };
+    drop(relays);
+
+    info!("No longer relaying server {}", uri);
+    relay.stop.trigger();
+    let _ = relay.task.await;
+    ctx.server_states.write().await.remove(uri);
+    metrics::forget_server(uri);
+    ctx.ta_updates.send(TAUpdates::NewState);
+}
+
+/// Follows the hosts the primary server knows about, relaying the ones it
+/// tells us about in its `Connect` response or adds later, and stopping the
+/// ones it deletes.
+pub async fn follow_hosts(ctx: &Ctx, packet: &Packet) {
+    match &packet.packet {
+        Some(packet::packet::Packet::Response(packet::Response {
+            details: Some(packet::response::Details::Connect(connect)),
+            ..
+        })) => {
+            if let Some(state) = &connect.state {
+                discover(ctx, &state.known_hosts).await;
+            }
+        }
+        Some(packet::packet::Packet::Event(packet::Event {
+            changed_object: Some(event::ChangedObject::HostAddedEvent(e)),
+        })) => {
+            if let Some(host) = &e.server {
+                discover(ctx, std::slice::from_ref(host)).await;
+            }
+        }
+        Some(packet::packet::Packet::Event(packet::Event {
+            changed_object: Some(event::ChangedObject::HostDeletedEvent(e)),
+        })) => {
+            if let Some(host) = &e.server {
+                stop(ctx, &host_uri(&ctx.config.ta_uri, host)).await;
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Starts relaying the known hosts a TA server told us about, skipping the
+/// primary server itself, which TA lists among them.
+pub async fn discover(ctx: &Ctx, hosts: &[models::CoreServer]) {
+    let primary_name = ctx
+        .ta_state
+        .read()
+        .await
+        .server_settings
+        .as_ref()
+        .map(|s| s.server_name.clone());
+    for host in hosts {
+        if primary_name.as_deref() == Some(host.name.as_str()) {
+            continue;
+        }
+        start(
+            ctx,
+            ServerConfig {
+                uri: host_uri(&ctx.config.ta_uri, host),
+                password: ctx.config.password.clone(),
+            },
+            true,
+        )
+        .await;
+    }
+}
+
+/// A known host's websocket uri. Hosts are reached the same way as the primary
+/// server, over `wss://` if it is, unless the address has its own scheme.
+fn host_uri(primary: &str, host: &models::CoreServer) -> String {
+    if host.address.contains("://") {
+        return host.address.clone();
+    }
+    let scheme = match primary.starts_with("wss://") {
+        true => "wss",
+        false => "ws",
+    };
+    let address = match host.address.contains(':') && !host.address.starts_with('[') {
+        true => format!("[{}]", host.address),
+        false => host.address.clone(),
+    };
+    format!("{}://{}:{}", scheme, address, host.websocket_port)
+}
+
+/// Whether two websocket uris point at the same server, ignoring case, the
+/// scheme, trailing slashes, default ports and the different names for localhost.
+fn same_server(a: &str, b: &str) -> bool {
+    fn key(uri: &str) -> (String, String) {
+        let uri = uri.trim().trim_end_matches('/').to_lowercase();
+        let (secure, rest) = match uri.split_once("://") {
+            Some((scheme, rest)) => (scheme == "wss", rest.to_string()),
+            None => (false, uri),
+        };
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (host.to_string(), port.to_string()),
+            _ => (
+                rest,
+                match secure {
+                    true => "443".to_string(),
+                    false => "80".to_string(),
+                },
+            ),
+        };
+        let host = match host.as_str() {
+            "localhost" | "127.0.0.1" | "[::1]" => "localhost".to_string(),
+            _ => host,
+        };
+        (host, port)
+    }
+    key(a) == key(b)
+}
+
+/// One relayed server's state, by uri.
+async fn server_state(ctx: &Ctx, uri: &str) -> Option<Arc<RwLock<TAState>>> {
+    ctx.server_states.read().await.get(uri).cloned()
+}
+
+/// Every relayed server's state except the primary one's. Each has its own lock,
+/// so one slow server doesn't hold up the others.
+async fn server_list(ctx: &Ctx) -> Vec<Arc<RwLock<TAState>>> {
+    ctx.server_states.read().await.values().cloned().collect()
+}
+
+/// The guid of the relay's TX user on a server, if it has connected.
+pub(crate) async fn tx_guid(ctx: &AppContext, uri: &str) -> Option<String> {
+    match uri == ctx.config.ta_uri {
+        true => ctx.outbound.tx_guid(),
+        false => ctx.relays.lock().await.get(uri)?.outbound.tx_guid(),
+    }
+}
+
+/// Waits for every server's connection to close once shutdown has started.
+pub async fn closed(ctx: &Ctx) {
+    let relays = std::mem::take(&mut *ctx.relays.lock().await);
+    for relay in relays.into_values() {
+        let _ = relay.task.await;
+    }
+}
+
+async fn run(ctx: Ctx, server: ServerConfig, stop: Shutdown, outbound: Arc<Outbound>) {
+    tokio::join!(
+        relay(&ctx, &server, &stop),
+        outbound::serve(&ctx, &outbound, &server, stopped(&ctx, &stop)),
+    );
+}
+
+/// Resolves once the relay shuts down or just this server is stopped.
+fn stopped(ctx: &Ctx, stop: &Shutdown) -> impl std::future::Future<Output = ()> + Send + 'static {
+    let (shutdown, stop) = (ctx.shutdown.wait(), stop.wait());
+    async move {
+        tokio::select! {
+            _ = shutdown => {},
+            _ = stop => {},
+        }
+    }
+}
+
+/// Relays `server`, reconnecting whenever the connection drops, until it's stopped.
+async fn relay(ctx: &Ctx, server: &ServerConfig, stop: &Shutdown) {
+    let packets = processor(ctx, server);
+    loop {
+        let mut con = tokio::select! {
+            con = TAConnection::connect_retrying(&server.uri, &ctx.config.rx_name, &server.password) => con,
+            _ = stopped(ctx, stop) => return,
+        };
+
+        let stopping = tokio::select! {
+            _ = receive(ctx, server, &mut con, &packets) => false,
+            _ = stopped(ctx, stop) => true,
+        };
+        if stopping {
+            con.close().await;
+            return;
+        }
+
+        warn!("Lost connection to {}, reconnecting...", server.uri);
+        if let Some(state) = server_state(ctx, &server.uri).await {
+            state.write().await.server_settings = None;
+        }
+        metrics::TA_RECONNECTS.inc();
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    }
+}
+
+async fn receive(
+    ctx: &Ctx,
+    server: &ServerConfig,
+    con: &mut TAConnection,
+    packets: &mpsc::UnboundedSender<(Packet, InFlight)>,
+) {
+    while let Some(msg) = con.next().await {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("Error receiving message from {}: {}", server.uri, e);
+                continue;
+            }
+        };
+        metrics::PACKETS_RECEIVED
+            .with_label_values(&[metrics::packet_type(&msg)])
+            .inc();
+
+        // the processor outlives every receive loop
+        let _ = packets.send((msg, ctx.shutdown.in_flight()));
+    }
+}
+
+/// Spawns the task that processes `server`'s packets one at a time, in the order
+/// they were received, like [`crate::ingest`] does for the primary server.
+fn processor(ctx: &Ctx, server: &ServerConfig) -> mpsc::UnboundedSender<(Packet, InFlight)> {
+    let (packets, mut queue) = mpsc::unbounded_channel::<(Packet, InFlight)>();
+    let ctx = ctx.clone();
+    let server = server.clone();
+    tokio::spawn(async move {
+        while let Some((msg, _in_flight)) = queue.recv().await {
+            process(&ctx, &server, msg).await;
+        }
+    });
+    packets
+}
+
+/// Routes a packet from `server` into its state and tells subscribers about it.
+async fn process(ctx: &Ctx, server: &ServerConfig, msg: Packet) {
+    if let Some(state) = server_state(ctx, &server.uri).await {
+        let mut state = state.write().await;
+        let timer = metrics::PACKET_PROCESSING_SECONDS
+            .with_label_values(&[&server.uri])
+            .start_timer();
+        let routed = packets::route_packet(ctx, &mut state, msg.clone()).await;
+        timer.observe_duration();
+        metrics::observe_state(&state);
+        drop(state);
+        match routed {
+            Ok(Some(reply)) => {
+                if let Err(e) = send_as_tx(ctx, server, reply).await {
+                    warn!("Failed to send packet to {}.", server.uri);
+                    debug!("Error: {}", e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                warn!("Error routing packet from {}. {:#?}", server.uri, msg);
+                debug!("Error: {}", e);
+            }
+        }
+    }
+
+    if !packets::is_realtime_score(&msg) {
+        ctx.ta_updates.send(TAUpdates::NewState);
+    }
+}
+
+/// Sends `packet` to a server as the relay's TX user, over the TX connection
+/// the relay keeps open to each server.
+pub async fn send_as_tx(ctx: &Ctx, server: &ServerConfig, packet: Packet) -> anyhow::Result<()> {
+    if server.uri == ctx.config.ta_uri {
+        return ctx.outbound.send(packet).await;
+    }
+
+    let outbound = ctx
+        .relays
+        .lock()
+        .await
+        .get(&server.uri)
+        .map(|r| r.outbound.clone())
+        .ok_or(anyhow::anyhow!(
+            "Server {} is no longer relayed.",
+            server.uri
+        ))?;
+    outbound.send(packet).await
+}
+
+fn gql_server(state: &TAState, primary: bool) -> GQLServer {
+    GQLServer {
+        uri: state.server.uri.clone(),
+        name: state.server_name(),
+        primary,
+        connected: state.server_settings.is_some(),
+    }
+}
+
+pub async fn servers_gql(ctx: &Ctx) -> Vec<GQLServer> {
+    let mut servers = vec![gql_server(&*ctx.ta_state.read().await, true)];
+    for state in server_list(ctx).await {
+        servers.push(gql_server(&*state.read().await, false));
+    }
+    servers
+}
+
+/// Runs `f` on one server's state, by uri or name. Without one, on the primary server's.
+pub async fn with_state<T>(
+    ctx: &Ctx,
+    server: Option<&str>,
+    f: impl FnOnce(&TAState) -> T,
+) -> anyhow::Result<T> {
+    let primary = ctx.ta_state.read().await;
+    let server = match server {
+        Some(server) if !primary.is_server(server) => server,
+        _ => return Ok(f(&primary)),
+    };
+    drop(primary);
+
+    for state in server_list(ctx).await {
+        let state = state.read().await;
+        if state.is_server(server) {
+            return Ok(f(&state));
+        }
+    }
+    Err(anyhow::anyhow!("No server {}.", server))
+}
+
+/// The state of one server, by uri or name. Without one, the primary server's.
+pub async fn state_gql(ctx: &Ctx, server: Option<&str>) -> anyhow::Result<GQLTAState> {
+    let primary = ctx.ta_state.read().await;
+    let server = match server {
+        Some(server) if !primary.is_server(server) => server,
+        _ => return primary.into_gql().await,
+    };
+    drop(primary);
+
+    for state in server_list(ctx).await {
+        let state = state.read().await;
+        if state.is_server(server) {
+            return state.into_gql().await;
+        }
+    }
+    Err(anyhow::anyhow!("No server {}.", server))
+}
+
+/// Finds a match on any server.
+pub async fn match_gql(ctx: &Ctx, id: Uuid) -> anyhow::Result<Option<Match>> {
+    let has_match = |state: &TAState| {
+        state
+            .matches
+            .iter()
+            .any(|m| Uuid::parse_str(&m.guid).is_ok_and(|guid| guid == id))
+    };
+
+    let primary = ctx.ta_state.read().await;
+    if has_match(&primary) {
+        return primary.get_single_match_gql(id).await;
+    }
+    drop(primary);
+    for state in server_list(ctx).await {
+        let state = state.read().await;
+        if has_match(&state) {
+            return state.get_single_match_gql(id).await;
+        }
+    }
+    ctx.ta_state.read().await.get_single_match_gql(id).await
+}
+
+/// The first thing `f` finds in any server's state, looking in the primary server's first.
+pub async fn find<T>(ctx: &Ctx, f: impl Fn(&TAState) -> Option<T>) -> Option<T> {
+    if let Some(found) = f(&*ctx.ta_state.read().await) {
+        return Some(found);
+    }
+    for state in server_list(ctx).await {
+        if let Some(found) = f(&*state.read().await) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+/// Finds a player on any server.
+pub async fn player_gql(ctx: &Ctx, id: Uuid) -> Option<User> {
+    find(ctx, |state| state.player_gql(id)).await
+}
+
+/// Every match on every server.
+pub async fn all_matches_gql(ctx: &Ctx) -> anyhow::Result<Vec<Match>> {
+    let mut matches = ctx.ta_state.read().await.into_gql().await?.matches;
+    for state in server_list(ctx).await {
+        matches.extend(state.read().await.into_gql().await?.matches);
+    }
+    Ok(matches)
+}
@@ -0,0 +1,138 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use async_graphql::{
+    extensions::{Extension, ExtensionContext, ExtensionFactory, NextSubscribe},
+    futures_util::stream::BoxStream,
+    Response,
+};
+use futures_util::StreamExt;
+use tokio::{sync::watch, time::Duration};
+use tracing::{info, warn};
+
+/// Coordinates shutting down the relay's tasks.
+#[derive(Clone)]
+pub struct Shutdown {
+    tx: Arc<watch::Sender<bool>>,
+    /// Packets that are still being routed.
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// Marks a packet as being routed until dropped, so [`Shutdown::flushed`] can wait for it.
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            tx: Arc::new(watch::channel(false).0),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Starts shutting down everything waiting on [`Shutdown::wait`].
+    pub fn trigger(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Resolves once shutdown has started.
+    pub fn wait(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let mut rx = self.tx.subscribe();
+        async move {
+            let _ = rx.wait_for(|shutdown| *shutdown).await;
+        }
+    }
+
+    /// Resolves on ctrl-c or SIGTERM, and starts shutting down.
+    pub async fn signal(&self) {
+        let ctrl_c = async {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                warn!("Failed to listen for ctrl-c: {}", e);
+                std::future::pending::<()>().await;
+            }
+        };
+
+        #[cfg(unix)]
+        let terminate = async {
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(mut sig) => {
+                    sig.recv().await;
+                }
+                Err(e) => {
+                    warn!("Failed to listen for SIGTERM: {}", e);
+                    std::future::pending::<()>().await;
+                }
+            }
+        };
+        #[cfg(not(unix))]
+        let terminate = std::future::pending::<()>();
+
+        tokio::select! {
+            _ = ctrl_c => info!("Shutting down..."),
+            _ = terminate => info!("Shutting down..."),
+            _ = self.wait() => {},
+        }
+        self.trigger();
+    }
+
+    pub fn in_flight(&self) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self.in_flight.clone())
+    }
+
+    /// Waits up to `timeout` for every packet being routed to finish, so their
+    /// results are written.
+    pub async fn flushed(&self, timeout: Duration) {
+        let waiting = async {
+            while self.in_flight.load(Ordering::SeqCst) > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        if tokio::time::timeout(timeout, waiting).await.is_err() {
+            warn!(
+                "Gave up waiting for {} packets to be processed.",
+                self.in_flight.load(Ordering::SeqCst)
+            );
+        }
+    }
+}
+
+/// Ends every subscription once shutdown starts, so clients see them complete.
+pub struct DrainSubscriptions(pub Shutdown);
+
+impl ExtensionFactory for DrainSubscriptions {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(DrainSubscriptionsExtension(self.0.clone()))
+    }
+}
+
+struct DrainSubscriptionsExtension(Shutdown);
+
+#[async_trait::async_trait]
+impl Extension for DrainSubscriptionsExtension {
+    fn subscribe<'s>(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        stream: BoxStream<'s, Response>,
+        next: NextSubscribe<'_>,
+    ) -> BoxStream<'s, Response> {
+        next.run(ctx, stream).take_until(self.0.wait()).boxed()
+    }
+}
@@ -0,0 +1,370 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    config::Simulator,
+    context::Ctx,
+    ingest,
+    proto::{
+        models,
+        packet::{self, event, push},
+    },
+};
+
+const PLAYER_NAMES: &[&str] = &[
+    "Astra", "Bumble", "Cobalt", "Dusk", "Ember", "Fable", "Gale", "Halo", "Iris", "Jinx", "Kite",
+    "Lumen", "Moss", "Nova", "Onyx", "Pike",
+];
+
+const LEVELS: &[(&str, &str)] = &[
+    (
+        "custom_level_3A1F8C2D9E4B7A6F5C3D2E1F0A9B8C7D6E5F4A3B",
+        "Lumina Overdrive",
+    ),
+    (
+        "custom_level_7C2E9A4F1B8D3C6E5A2F9B4D7C1E8A3F6B5D2C9E",
+        "Paper Satellites",
+    ),
+    (
+        "custom_level_1D8B3F6A9C2E5D4B7F1A8C3E6D9B2F5A4C7E1D8B",
+        "Glass Cathedral",
+    ),
+    (
+        "custom_level_9E4A7C1F3B6D8E2A5C9F4B7D1E3A6C8F2B5D9E4A",
+        "Midnight Relay",
+    ),
+];
+
+/// How often simulated players send their scores.
+const TICK: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// A made-up player and how well they play.
+struct SimPlayer {
+    user: models::User,
+    accuracy: f64,
+    miss_rate: f64,
+}
+
+/// One player's progress through the current song.
+struct Run {
+    score: models::RealtimeScore,
+    /// Accuracy drifts up and down over a song.
+    drift: f64,
+    notes: i32,
+    failed: bool,
+}
+
+impl Run {
+    fn new(user_guid: &str) -> Self {
+        Self {
+            score: models::RealtimeScore {
+                user_guid: user_guid.to_string(),
+                player_health: 0.5,
+                left_hand: Some(models::ScoreTrackerHand::default()),
+                right_hand: Some(models::ScoreTrackerHand::default()),
+                ..Default::default()
+            },
+            drift: 0.0,
+            notes: 0,
+            failed: false,
+        }
+    }
+
+    fn play_note(&mut self, player: &SimPlayer, rng: &mut StdRng) {
+        // the best possible score has every note perfectly cut in one combo
+        self.score.max_score += 115 * multiplier(self.notes);
+        self.notes += 1;
+        self.drift = (self.drift + rng.gen_range(-0.004..=0.004)).clamp(-0.03, 0.03);
+
+        let score = &mut self.score;
+        let hand = match self.notes % 2 {
+            0 => score.left_hand.get_or_insert_with(Default::default),
+            _ => score.right_hand.get_or_insert_with(Default::default),
+        };
+        if rng.gen_bool(player.miss_rate) {
+            hand.miss += 1;
+            score.notes_missed += 1;
+            score.combo = 0;
+            score.player_health -= 0.15;
+        } else if rng.gen_bool(player.miss_rate / 4.0) {
+            hand.bad_cut += 1;
+            score.bad_cuts += 1;
+            score.combo = 0;
+            score.player_health -= 0.1;
+        } else {
+            let cut = (player.accuracy + self.drift + rng.gen_range(-0.03..=0.03)).clamp(0.5, 1.0);
+            hand.hit += 1;
+            score.score += (115.0 * cut).round() as i32 * multiplier(score.combo);
+            score.combo += 1;
+            score.max_combo = score.max_combo.max(score.combo);
+            score.player_health = (score.player_health + 0.01).min(1.0);
+        }
+
+        score.score_with_modifiers = score.score;
+        score.max_score_with_modifiers = score.max_score;
+        score.accuracy = score.score as f32 / score.max_score as f32;
+        if score.player_health <= 0.0 {
+            score.player_health = 0.0;
+            self.failed = true;
+        }
+    }
+}
+
+/// Beat Saber's score multiplier at a combo.
+fn multiplier(combo: i32) -> i32 {
+    match combo {
+        0..=1 => 1,
+        2..=5 => 2,
+        6..=13 => 4,
+        _ => 8,
+    }
+}
+
+fn packet(packet: packet::packet::Packet) -> packet::Packet {
+    packet::Packet {
+        id: Uuid::new_v4().to_string(),
+        from: Uuid::nil().to_string(),
+        packet: Some(packet),
+    }
+}
+
+fn event(changed_object: event::ChangedObject) -> packet::Packet {
+    packet(packet::packet::Packet::Event(packet::Event {
+        changed_object: Some(changed_object),
+    }))
+}
+
+fn push(data: push::Data) -> packet::Packet {
+    packet(packet::packet::Packet::Push(packet::Push {
+        data: Some(data),
+    }))
+}
+
+fn guid(rng: &mut StdRng) -> String {
+    uuid::Builder::from_random_bytes(rng.gen())
+        .into_uuid()
+        .to_string()
+}
+
+fn user(rng: &mut StdRng, name: String, client_type: models::user::ClientTypes) -> models::User {
+    models::User {
+        guid: guid(rng),
+        name,
+        user_id: rng
+            .gen_range(76561197960265728u64..76561199999999999)
+            .to_string(),
+        client_type: client_type.into(),
+        ..Default::default()
+    }
+}
+
+/// Makes up the players, coordinators, teams and matches of a tournament.
+fn tournament(
+    settings: &Simulator,
+    relay_name: &str,
+    rng: &mut StdRng,
+) -> (models::State, Vec<SimPlayer>) {
+    let teams = (0..settings.teams)
+        .map(|i| models::Team {
+            id: guid(rng),
+            name: format!("Team {}", i + 1),
+        })
+        .collect::<Vec<_>>();
+
+    let players = (0..settings.players)
+        .map(|i| {
+            let name = match i / PLAYER_NAMES.len() {
+                0 => PLAYER_NAMES[i].to_string(),
+                n => format!("{} {}", PLAYER_NAMES[i % PLAYER_NAMES.len()], n + 1),
+            };
+            let mut user = user(rng, name, models::user::ClientTypes::Player);
+            if !teams.is_empty() {
+                user.team = Some(teams[i % teams.len()].clone());
+            }
+            let accuracy = (0.92 + rng.gen_range(-settings.skill_spread..=settings.skill_spread))
+                .clamp(0.6, 0.99);
+            SimPlayer {
+                user,
+                accuracy,
+                miss_rate: ((1.0 - accuracy) * 0.1).clamp(0.001, 0.05),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut users = vec![user(
+        rng,
+        relay_name.to_string(),
+        models::user::ClientTypes::WebsocketConnection,
+    )];
+    let mut matches = vec![];
+    for (i, group) in players.chunks(settings.players_per_match).enumerate() {
+        let coordinator = user(
+            rng,
+            format!("Coordinator {}", i + 1),
+            models::user::ClientTypes::Coordinator,
+        );
+        matches.push(models::Match {
+            guid: guid(rng),
+            associated_users: group
+                .iter()
+                .map(|p| p.user.guid.clone())
+                .chain([coordinator.guid.clone(), users[0].guid.clone()])
+                .collect(),
+            leader: coordinator.guid.clone(),
+            ..Default::default()
+        });
+        users.push(coordinator);
+    }
+    users.extend(players.iter().map(|p| p.user.clone()));
+
+    let state = models::State {
+        server_settings: Some(models::ServerSettings {
+            server_name: "TARS Simulator".to_string(),
+            enable_teams: !teams.is_empty(),
+            teams,
+            score_update_frequency: TICK.as_millis() as i32,
+            ..Default::default()
+        }),
+        users,
+        matches,
+        ..Default::default()
+    };
+    (state, players)
+}
+
+/// Feeds made-up matches through the same path as packets from a TA server,
+/// playing song after song until shutdown.
+pub async fn run(ctx: Ctx) {
+    let settings = ctx.config.simulator.clone();
+    let mut rng = match settings.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let (state, mut players) = tournament(&settings, &ctx.config.rx_name, &mut rng);
+    let mut matches = state.matches.clone();
+    info!(
+        "Simulating {} players in {} matches",
+        players.len(),
+        matches.len()
+    );
+
+    ingest::process(
+        &ctx,
+        packet(packet::packet::Packet::Response(packet::Response {
+            r#type: packet::response::ResponseType::Success.into(),
+            details: Some(packet::response::Details::Connect(
+                packet::response::Connect {
+                    state: Some(state),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        })),
+    )
+    .await;
+    ctx.health.set_ta_live(true);
+
+    tokio::select! {
+        _ = async {
+            loop {
+                play_song(&ctx, &settings, &mut rng, &mut players, &mut matches).await;
+                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+            }
+        } => {},
+        _ = ctx.shutdown.wait() => {},
+    }
+    ctx.health.set_ta_live(false);
+}
+
+/// Picks a level for every match and plays it through to `SongFinished`.
+async fn play_song(
+    ctx: &Ctx,
+    settings: &Simulator,
+    rng: &mut StdRng,
+    players: &mut [SimPlayer],
+    matches: &mut [models::Match],
+) {
+    let (level_id, name) = LEVELS[rng.gen_range(0..LEVELS.len())];
+    let difficulty = rng.gen_range(0..=4);
+    for m in matches.iter_mut() {
+        m.selected_level = Some(models::PreviewBeatmapLevel {
+            level_id: level_id.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        });
+        m.selected_difficulty = difficulty;
+        ingest::process(
+            ctx,
+            event(event::ChangedObject::MatchUpdatedEvent(
+                event::MatchUpdatedEvent {
+                    r#match: Some(m.clone()),
+                },
+            )),
+        )
+        .await;
+    }
+    set_play_state(ctx, players, models::user::PlayStates::InGame).await;
+
+    let mut runs = players
+        .iter()
+        .map(|p| Run::new(&p.user.guid))
+        .collect::<Vec<_>>();
+    let ticks = settings.song_seconds * 1000 / TICK.as_millis() as u64;
+    for tick in 1..=ticks {
+        tokio::time::sleep(TICK).await;
+        for (player, run) in players.iter().zip(runs.iter_mut()) {
+            if run.failed {
+                continue;
+            }
+            // about 6 notes a second
+            for _ in 0..rng.gen_range(0..=3) {
+                run.play_note(player, rng);
+            }
+            run.score.song_position = (tick * TICK.as_millis() as u64) as f32 / 1000.0;
+            ingest::process(ctx, push(push::Data::RealtimeScore(run.score.clone()))).await;
+        }
+    }
+
+    for (player, run) in players.iter().zip(&runs) {
+        let completion = match run.failed {
+            true => push::song_finished::CompletionType::Failed,
+            false => push::song_finished::CompletionType::Passed,
+        };
+        ingest::process(
+            ctx,
+            push(push::Data::SongFinished(push::SongFinished {
+                player: Some(player.user.clone()),
+                beatmap: Some(models::Beatmap {
+                    name: name.to_string(),
+                    level_id: level_id.to_string(),
+                    difficulty,
+                    ..Default::default()
+                }),
+                r#type: completion.into(),
+                score: run.score.score,
+            })),
+        )
+        .await;
+    }
+    set_play_state(ctx, players, models::user::PlayStates::Waiting).await;
+}
+
+async fn set_play_state(
+    ctx: &Ctx,
+    players: &mut [SimPlayer],
+    play_state: models::user::PlayStates,
+) {
+    for player in players.iter_mut() {
+        player.user.play_state = play_state.into();
+        ingest::process(
+            ctx,
+            event(event::ChangedObject::UserUpdatedEvent(
+                event::UserUpdatedEvent {
+                    user: Some(player.user.clone()),
+                },
+            )),
+        )
+        .await;
+    }
+}
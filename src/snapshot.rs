@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use prost::Message as _;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{context::Ctx, packets::TAState, proto::models, structs::GQLOverState};
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+/// The primary server's state as TA would send it, plus the scores the relay
+/// has seen.
+#[derive(Clone, PartialEq, prost::Message)]
+struct TASnapshot {
+    #[prost(message, optional, tag = "1")]
+    state: Option<models::State>,
+    #[prost(map = "string, message", tag = "2")]
+    rts: HashMap<String, models::RealtimeScore>,
+}
+
+/// Relay state that would otherwise be lost on restart. Pools, pick/bans and the
+/// bracket are saved on their own as they change.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    /// Unix time in milliseconds.
+    saved_at_ms: i64,
+    /// A base64 encoded [`TASnapshot`].
+    ta_state: String,
+    over_state: GQLOverState,
+}
+
+impl TASnapshot {
+    fn new(state: &TAState) -> Self {
+        Self {
+            state: Some(state.state_proto()),
+            rts: state.rts.clone(),
+        }
+    }
+
+    fn restore(self, state: &mut TAState) {
+        let snapshot = self.state.unwrap_or_default();
+        let users = |client_type: models::user::ClientTypes| {
+            snapshot
+                .users
+                .iter()
+                .filter(|u| u.client_type == client_type as i32)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        state.server_users = users(models::user::ClientTypes::WebsocketConnection);
+        state.coordinators = users(models::user::ClientTypes::Coordinator);
+        state.players = users(models::user::ClientTypes::Player);
+        state.matches = snapshot.matches;
+        state.servers = snapshot.known_hosts;
+        state.server_settings = snapshot.server_settings;
+        state.rts = self.rts;
+    }
+}
+
+/// Writes a snapshot of the relay's state to the data directory.
+pub async fn save(ctx: &Ctx) -> anyhow::Result<()> {
+    let snapshot = Snapshot {
+        saved_at_ms: chrono::Utc::now().timestamp_millis(),
+        ta_state: STANDARD.encode(TASnapshot::new(&*ctx.ta_state.read().await).encode_to_vec()),
+        over_state: ctx.over_state.read().await.clone(),
+    };
+
+    // written aside first, so a crash mid-write leaves the last snapshot intact
+    let path = ctx.config.data_path(SNAPSHOT_FILE);
+    let partial = path.with_extension("json.partial");
+    tokio::fs::create_dir_all(&ctx.config.data_dir).await?;
+    tokio::fs::write(&partial, serde_json::to_vec_pretty(&snapshot)?).await?;
+    tokio::fs::rename(&partial, &path).await?;
+    Ok(())
+}
+
+/// Restores the last snapshot, if there is one. The next `Connect` response
+/// then replaces whatever changed on the TA server in the meantime.
+pub async fn restore(ctx: &Ctx) -> anyhow::Result<()> {
+    let data = match tokio::fs::read(ctx.config.data_path(SNAPSHOT_FILE)).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let snapshot: Snapshot = serde_json::from_slice(&data)?;
+    let ta_state = TASnapshot::decode(STANDARD.decode(&snapshot.ta_state)?.as_slice())?;
+
+    ta_state.restore(&mut *ctx.ta_state.write().await);
+    *ctx.over_state.write().await = snapshot.over_state;
+    info!(
+        "Restored snapshot from {}",
+        chrono::DateTime::from_timestamp_millis(snapshot.saved_at_ms)
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default()
+    );
+    Ok(())
+}
+
+/// Snapshots the relay every `snapshot_interval` seconds, and once more after
+/// the last packets are routed on shutdown.
+pub async fn run(ctx: Ctx) {
+    if ctx.config.snapshot_interval == 0 || !ctx.config.is_live() {
+        return;
+    }
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(ctx.config.snapshot_interval));
+    // the first tick is immediate, and there's nothing new to save yet
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {},
+            _ = ctx.shutdown.wait() => break,
+        }
+        if let Err(e) = save(&ctx).await {
+            warn!("Failed to save snapshot.");
+            debug!("Error: {}", e);
+        }
+    }
+
+    ctx.shutdown
+        .flushed(std::time::Duration::from_secs(5))
+        .await;
+    match save(&ctx).await {
+        Ok(()) => info!("Saved snapshot."),
+        Err(e) => {
+            warn!("Failed to save snapshot.");
+            debug!("Error: {}", e);
+        }
+    }
+}
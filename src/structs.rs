@@ -1,11 +1,16 @@
-use async_graphql::{Enum, InputObject, SimpleObject};
+use async_graphql::{ComplexObject, Context, Enum, InputObject, SimpleObject};
+use serde::{Deserialize, Serialize};
 use tap::Tap;
 use tracing::warn;
 use uuid::Uuid;
 
-use crate::{packets::TAState, parse_uuid};
+use crate::{
+    gql::app, packets::TAState, parse_uuid, pool::PoolMap, proto::models, roster::Profile,
+};
 
-#[derive(SimpleObject, Default)]
+#[derive(SimpleObject, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+#[graphql(complex)]
 pub struct User {
     guid: Uuid,
     name: String,
@@ -18,8 +23,27 @@ pub struct User {
     stream_sync_start_ms: i32,
 }
 
+#[ComplexObject]
+impl User {
+    async fn profile(&self, ctx: &Context<'_>) -> Option<Profile> {
+        app(ctx).roster.read().await.get(&self.user_id).cloned()
+    }
+
+    /// The roster's display name override, falling back to the name from TA.
+    async fn display_name(&self, ctx: &Context<'_>) -> String {
+        app(ctx)
+            .roster
+            .read()
+            .await
+            .get(&self.user_id)
+            .and_then(|p| p.display_name.clone())
+            .unwrap_or(self.name.clone())
+    }
+}
+
 #[repr(i32)]
-#[derive(Enum, Default, Clone, Copy, Eq, PartialEq)]
+#[derive(Enum, Serialize, Default, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum PlayState {
     #[default]
     Waiting = 0,
@@ -27,7 +51,8 @@ pub enum PlayState {
 }
 
 #[repr(i32)]
-#[derive(Enum, Default, Clone, Copy, Eq, PartialEq)]
+#[derive(Enum, Serialize, Default, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum DownloadState {
     #[default]
     None = 0,
@@ -36,31 +61,52 @@ pub enum DownloadState {
     DownloadError = 3,
 }
 
-#[derive(SimpleObject, Default, Eq, PartialEq, Ord, PartialOrd, Clone)]
+#[derive(SimpleObject, Serialize, Default, Eq, PartialEq, Ord, PartialOrd, Clone)]
 pub struct Team {
     guid: Uuid,
     name: String,
 }
 
-#[derive(SimpleObject)]
+#[derive(SimpleObject, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Match {
     guid: Uuid,
+    /// The name of the TA server the match is on.
+    server: String,
     players: Vec<User>,
     teams: Vec<Team>,
     coordinators: Vec<User>,
+    /// Relay users in the match, such as overlays.
+    relay_users: Vec<User>,
     current_map: Option<Map>,
     scores: Vec<Score>,
 }
 
-#[derive(SimpleObject)]
+#[derive(SimpleObject, Serialize)]
+#[graphql(complex)]
 pub struct Map {
     hash: String,
     name: String,
     difficulty: i32,
     modifiers: Vec<String>,
+    #[graphql(skip)]
+    #[serde(skip)]
+    match_guid: Uuid,
 }
 
-#[derive(SimpleObject)]
+#[ComplexObject]
+impl Map {
+    async fn pool_slot(&self, ctx: &Context<'_>) -> Option<PoolMap> {
+        app(ctx)
+            .pool_state
+            .read()
+            .await
+            .slot_for(self.match_guid, &self.hash, self.difficulty)
+    }
+}
+
+#[derive(SimpleObject, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Score {
     owner_guid: Uuid,
 
@@ -85,21 +131,118 @@ pub struct Score {
     right_hand_bad_cut: i32,
 }
 
-#[derive(SimpleObject)]
+impl Score {
+    pub fn from_rts(
+        owner_guid: &str,
+        rts: &crate::proto::models::RealtimeScore,
+    ) -> anyhow::Result<Score> {
+        let right_hand = rts
+            .right_hand
+            .clone()
+            .ok_or(anyhow::anyhow!("No right hand for user {}.", owner_guid))?;
+        let left_hand = rts
+            .left_hand
+            .clone()
+            .ok_or(anyhow::anyhow!("No left hand for user {}.", owner_guid))?;
+        Ok(Score {
+            owner_guid: parse_uuid(owner_guid),
+            score: rts.score,
+            score_with_modifiers: rts.score_with_modifiers,
+            max_score: rts.max_score,
+            max_score_with_modifiers: rts.max_score_with_modifiers,
+            combo: rts.combo,
+            player_health: rts.player_health as f64,
+            accuracy: rts.accuracy as f64,
+            song_position: rts.song_position as f64,
+            notes_missed: rts.notes_missed,
+            bad_cuts: rts.bad_cuts,
+            bomb_hits: rts.bomb_hits,
+            wall_hits: rts.wall_hits,
+            max_combo: rts.max_combo,
+            left_hand_hits: left_hand.hit,
+            left_hand_misses: left_hand.miss,
+            left_hand_bad_cut: left_hand.bad_cut,
+            right_hand_hits: right_hand.hit,
+            right_hand_misses: right_hand.miss,
+            right_hand_bad_cut: right_hand.bad_cut,
+        })
+    }
+}
+
+#[derive(SimpleObject, Serialize)]
 pub struct GQLTAState {
     pub coordinators: Vec<User>,
     pub players: Vec<User>,
     pub matches: Vec<Match>,
 }
 
-#[derive(SimpleObject, Clone)]
+impl GQLTAState {
+    /// Just the match with `id`, and the players and coordinators in it.
+    pub fn only_match(mut self, id: Uuid) -> Self {
+        self.matches.retain(|m| m.guid == id);
+        let in_match = |u: &User, users: fn(&Match) -> &Vec<User>| {
+            self.matches
+                .iter()
+                .any(|m| users(m).iter().any(|m| m.guid == u.guid))
+        };
+        self.players.retain(|u| in_match(u, |m| &m.players));
+        self.coordinators
+            .retain(|u| in_match(u, |m| &m.coordinators));
+        self
+    }
+
+    /// Without each match's scores, which the JSON feeds send on their own.
+    pub fn without_scores(mut self) -> Self {
+        for m in &mut self.matches {
+            m.scores.clear();
+        }
+        self
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct GQLServer {
+    pub uri: String,
+    pub name: String,
+    /// Whether this is the server at `ta_uri`, which brackets and health checks follow.
+    pub primary: bool,
+    /// Whether the relay is connected and has the server's state.
+    pub connected: bool,
+}
+
+#[derive(SimpleObject)]
+pub struct GQLServerInfo {
+    pub name: String,
+    pub uri: String,
+    /// The server's settings from the `Connect` response, `None` until connected.
+    pub settings: Option<ServerSettings>,
+}
+
+#[derive(SimpleObject)]
+pub struct ServerSettings {
+    pub enable_teams: bool,
+    pub teams: Vec<Team>,
+    pub score_update_frequency: i32,
+    pub banned_mods: Vec<String>,
+}
+
+/// Another TA server this one knows about.
+#[derive(SimpleObject)]
+pub struct KnownHost {
+    pub name: String,
+    pub address: String,
+    pub port: i32,
+    pub websocket_port: i32,
+}
+
+#[derive(SimpleObject, Serialize, Deserialize, Clone)]
 pub struct Page {
     pub data: Vec<PageData>,
     pub path: String,
     pub path_name: String,
 }
 
-#[derive(SimpleObject, Clone)]
+#[derive(SimpleObject, Serialize, Deserialize, Clone)]
 pub struct PageData {
     pub key: String,
     pub value: String,
@@ -118,7 +261,7 @@ pub struct InputPageData {
     pub value: String,
 }
 
-#[derive(SimpleObject, Clone)]
+#[derive(SimpleObject, Serialize, Deserialize, Clone)]
 pub struct GQLOverState {
     pub page: Page,
 }
@@ -153,6 +296,50 @@ impl InputPage {
 }
 
 impl TAState {
+    pub fn server_info_gql(&self) -> GQLServerInfo {
+        GQLServerInfo {
+            name: self.server_name(),
+            uri: self.server.uri.clone(),
+            settings: self.server_settings.as_ref().map(|s| ServerSettings {
+                enable_teams: s.enable_teams,
+                teams: s
+                    .teams
+                    .iter()
+                    .map(|t| Team {
+                        guid: parse_uuid(&t.id),
+                        name: t.name.clone(),
+                    })
+                    .collect(),
+                score_update_frequency: s.score_update_frequency,
+                banned_mods: s.banned_mods.clone(),
+            }),
+        }
+    }
+
+    pub fn known_hosts_gql(&self) -> Vec<KnownHost> {
+        self.servers
+            .iter()
+            .map(|h| KnownHost {
+                name: h.name.clone(),
+                address: h.address.clone(),
+                port: h.port,
+                websocket_port: h.websocket_port,
+            })
+            .collect()
+    }
+
+    /// The websocket connections on the server, such as overlays and relays.
+    pub fn relay_users_gql(&self) -> Vec<User> {
+        self.server_users.iter().map(user_gql).collect()
+    }
+
+    pub fn player_gql(&self, id: Uuid) -> Option<User> {
+        self.players
+            .iter()
+            .find(|p| parse_uuid(&p.guid) == id)
+            .map(user_gql)
+    }
+
     pub async fn get_single_match_gql(&self, id: Uuid) -> anyhow::Result<Option<Match>> {
         let match_ = match self.matches.iter().find(|m| parse_uuid(&m.guid) == id) {
             Some(match_) => match_,
@@ -163,33 +350,11 @@ impl TAState {
         };
         Ok(Some(Match {
             guid: parse_uuid(&match_.guid),
+            server: self.server_name(),
             players: match_
                 .associated_users
                 .iter()
-                .filter_map(|u| {
-                    self.players.iter().find(|p| p.guid == *u).map(|p| User {
-                        guid: parse_uuid(&p.guid),
-                        name: p.name.clone(),
-                        user_id: p.user_id.clone(),
-                        play_state: unsafe {
-                            std::mem::transmute_copy::<i32, crate::structs::PlayState>(
-                                &p.play_state,
-                            )
-                        },
-                        download_state: unsafe {
-                            std::mem::transmute_copy::<i32, crate::structs::DownloadState>(
-                                &p.download_state,
-                            )
-                        },
-                        team: p.team.as_ref().map(|t| Team {
-                            guid: parse_uuid(&t.id),
-                            name: t.name.clone(),
-                        }),
-                        mod_list: p.mod_list.clone(),
-                        stream_delay_ms: p.stream_delay_ms as i32,
-                        stream_sync_start_ms: p.stream_sync_start_ms as i32,
-                    })
-                })
+                .filter_map(|u| self.players.iter().find(|p| p.guid == *u).map(user_gql))
                 .collect(),
             teams: match_
                 .associated_users
@@ -212,30 +377,15 @@ impl TAState {
                     self.coordinators
                         .iter()
                         .find(|p| p.guid == *u)
-                        .map(|c| User {
-                            guid: parse_uuid(&c.guid),
-                            name: c.name.clone(),
-                            user_id: c.user_id.clone(),
-                            play_state: unsafe {
-                                std::mem::transmute_copy::<i32, crate::structs::PlayState>(
-                                    &c.play_state,
-                                )
-                            },
-                            download_state: unsafe {
-                                std::mem::transmute_copy::<i32, crate::structs::DownloadState>(
-                                    &c.download_state,
-                                )
-                            },
-                            team: c.team.as_ref().map(|t| Team {
-                                guid: parse_uuid(&t.id),
-                                name: t.name.clone(),
-                            }),
-                            mod_list: c.mod_list.clone(),
-                            stream_delay_ms: c.stream_delay_ms as i32,
-                            stream_sync_start_ms: c.stream_sync_start_ms as i32,
-                        })
+                        .map(user_gql)
                 })
                 .collect(),
+            relay_users: match_
+                .associated_users
+                .iter()
+                .filter_map(|u| self.server_users.iter().find(|s| s.guid == *u))
+                .map(user_gql)
+                .collect(),
             current_map: {
                 let level: Option<&crate::proto::models::PreviewBeatmapLevel> =
                     match_.selected_level.as_ref();
@@ -251,6 +401,7 @@ impl TAState {
                     name: level.name.clone(),
                     difficulty: match_.selected_difficulty,
                     modifiers: vec![],
+                    match_guid: parse_uuid(&match_.guid),
                 })
             },
             scores: {
@@ -273,41 +424,9 @@ impl TAState {
                                 return Err(anyhow::anyhow!("No RTS for user {}.", u));
                             }
                         };
-                        let right_hand = match rts.right_hand.clone() {
-                            Some(rts) => rts,
-                            None => {
-                                warn!("No right hand for user {}.", u);
-                                return Err(anyhow::anyhow!("No right hand for user {}.", u));
-                            }
-                        };
-                        let left_hand = match rts.left_hand.clone() {
-                            Some(rts) => rts,
-                            None => {
-                                warn!("No left hand for user {}.", u);
-                                return Err(anyhow::anyhow!("No left hand for user {}.", u));
-                            }
-                        };
-                        Ok(Score {
-                            owner_guid: parse_uuid(u),
-                            score: rts.score,
-                            score_with_modifiers: rts.score_with_modifiers,
-                            max_score: rts.max_score,
-                            max_score_with_modifiers: rts.max_score_with_modifiers,
-                            combo: rts.combo,
-                            player_health: rts.player_health as f64,
-                            accuracy: rts.accuracy as f64,
-                            song_position: rts.song_position as f64,
-                            notes_missed: rts.notes_missed,
-                            bad_cuts: rts.bad_cuts,
-                            bomb_hits: rts.bomb_hits,
-                            wall_hits: rts.wall_hits,
-                            max_combo: rts.max_combo,
-                            left_hand_hits: left_hand.hit,
-                            left_hand_misses: left_hand.miss,
-                            left_hand_bad_cut: left_hand.bad_cut,
-                            right_hand_hits: right_hand.hit,
-                            right_hand_misses: right_hand.miss,
-                            right_hand_bad_cut: right_hand.bad_cut,
+                        Score::from_rts(u, rts).map_err(|e| {
+                            warn!("{}", e);
+                            e
                         })
                     })
                     .collect::<anyhow::Result<Vec<Score>>>()?
@@ -317,86 +436,20 @@ impl TAState {
 
     pub async fn into_gql(&self) -> anyhow::Result<GQLTAState> {
         Ok(GQLTAState {
-            players: self
-                .players
-                .iter()
-                .map(|p| User {
-                    guid: parse_uuid(&p.guid),
-                    user_id: p.user_id.clone(),
-                    name: p.name.clone(),
-                    play_state: unsafe {
-                        std::mem::transmute_copy::<i32, crate::structs::PlayState>(&p.play_state)
-                    },
-                    download_state: unsafe {
-                        std::mem::transmute_copy::<i32, crate::structs::DownloadState>(
-                            &p.download_state,
-                        )
-                    },
-                    team: p.team.as_ref().map(|t| Team {
-                        guid: parse_uuid(&t.id),
-                        name: t.name.clone(),
-                    }),
-                    mod_list: p.mod_list.clone(),
-                    stream_delay_ms: p.stream_delay_ms as i32,
-                    stream_sync_start_ms: p.stream_sync_start_ms as i32,
-                })
-                .collect(),
-            coordinators: self
-                .coordinators
-                .iter()
-                .map(|p| User {
-                    guid: parse_uuid(&p.guid),
-                    name: p.name.clone(),
-                    user_id: p.user_id.clone(),
-                    play_state: unsafe {
-                        std::mem::transmute_copy::<i32, crate::structs::PlayState>(&p.play_state)
-                    },
-                    download_state: unsafe {
-                        std::mem::transmute_copy::<i32, crate::structs::DownloadState>(
-                            &p.download_state,
-                        )
-                    },
-                    team: p.team.as_ref().map(|t| Team {
-                        guid: parse_uuid(&t.id),
-                        name: t.name.clone(),
-                    }),
-                    mod_list: p.mod_list.clone(),
-                    stream_delay_ms: p.stream_delay_ms as i32,
-                    stream_sync_start_ms: p.stream_sync_start_ms as i32,
-                })
-                .collect(),
+            players: self.players.iter().map(user_gql).collect(),
+            coordinators: self.coordinators.iter().map(user_gql).collect(),
             matches: self
                 .matches
                 .iter()
                 .map(|m| {
                     Ok(Match {
                         guid: parse_uuid(&m.guid),
+                        server: self.server_name(),
                         players: m
                             .associated_users
                             .iter()
                             .filter_map(|u| {
-                                self.players.iter().find(|p| p.guid == *u).map(|p| User {
-                                guid: parse_uuid(&p.guid),
-                                name: p.name.clone(),
-                                user_id: p.user_id.clone(),
-                                play_state: unsafe {
-                                    std::mem::transmute_copy::<i32, crate::structs::PlayState>(
-                                        &p.play_state,
-                                    )
-                                },
-                                download_state: unsafe {
-                                    std::mem::transmute_copy::<i32, crate::structs::DownloadState>(
-                                        &p.download_state,
-                                    )
-                                },
-                                team: p.team.as_ref().map(|t| Team {
-                                    guid: parse_uuid(&t.id),
-                                    name: t.name.clone(),
-                                }),
-                                mod_list: p.mod_list.clone(),
-                                stream_delay_ms: p.stream_delay_ms as i32,
-                                stream_sync_start_ms: p.stream_sync_start_ms as i32,
-                            })
+                                self.players.iter().find(|p| p.guid == *u).map(user_gql)
                             })
                             .collect(),
                         teams: m
@@ -418,38 +471,17 @@ impl TAState {
                             .iter()
                             .filter_map(|u| {
                                 self.coordinators
-                                        .iter()
-                                        .find(|p| p.guid == *u)
-                                        .map(|c| User {
-                                            guid: parse_uuid(&c.guid),
-                                            name: c.name.clone(),
-                                            user_id: c.user_id.clone(),
-                                            play_state: unsafe {
-                                                std::mem::transmute_copy::<
-                                                    i32,
-                                                    crate::structs::PlayState,
-                                                >(
-                                                    &c.play_state
-                                                )
-                                            },
-                                            download_state: unsafe {
-                                                std::mem::transmute_copy::<
-                                                    i32,
-                                                    crate::structs::DownloadState,
-                                                >(
-                                                    &c.download_state
-                                                )
-                                            },
-                                            team: c.team.as_ref().map(|t| Team {
-                                                guid: parse_uuid(&t.id),
-                                                name: t.name.clone(),
-                                            }),
-                                            mod_list: c.mod_list.clone(),
-                                            stream_delay_ms: c.stream_delay_ms as i32,
-                                            stream_sync_start_ms: c.stream_sync_start_ms as i32,
-                                        })
+                                    .iter()
+                                    .find(|p| p.guid == *u)
+                                    .map(user_gql)
                             })
                             .collect(),
+                        relay_users: m
+                            .associated_users
+                            .iter()
+                            .filter_map(|u| self.server_users.iter().find(|s| s.guid == *u))
+                            .map(user_gql)
+                            .collect(),
                         current_map: {
                             let level = m.selected_level.as_ref();
                             level.map(|level| Map {
@@ -464,6 +496,7 @@ impl TAState {
                                 name: level.name.clone(),
                                 difficulty: m.selected_difficulty,
                                 modifiers: vec![],
+                                match_guid: parse_uuid(&m.guid),
                             })
                         },
                         scores: m
@@ -485,47 +518,9 @@ impl TAState {
                                         return Err(anyhow::anyhow!("No RTS for user {}.", u));
                                     }
                                 };
-                                let right_hand = match rts.right_hand.clone() {
-                                    Some(rts) => rts,
-                                    None => {
-                                        warn!("No right hand for user {}.", u);
-                                        return Err(anyhow::anyhow!(
-                                            "No right hand for user {}.",
-                                            u
-                                        ));
-                                    }
-                                };
-                                let left_hand = match rts.left_hand.clone() {
-                                    Some(rts) => rts,
-                                    None => {
-                                        warn!("No left hand for user {}.", u);
-                                        return Err(anyhow::anyhow!(
-                                            "No left hand for user {}.",
-                                            u
-                                        ));
-                                    }
-                                };
-                                Ok(Score {
-                                    owner_guid: parse_uuid(u),
-                                    score: rts.score,
-                                    score_with_modifiers: rts.score_with_modifiers,
-                                    max_score: rts.max_score,
-                                    max_score_with_modifiers: rts.max_score_with_modifiers,
-                                    combo: rts.combo,
-                                    player_health: rts.player_health as f64,
-                                    accuracy: rts.accuracy as f64,
-                                    song_position: rts.song_position as f64,
-                                    notes_missed: rts.notes_missed,
-                                    bad_cuts: rts.bad_cuts,
-                                    bomb_hits: rts.bomb_hits,
-                                    wall_hits: rts.wall_hits,
-                                    max_combo: rts.max_combo,
-                                    left_hand_hits: left_hand.hit,
-                                    left_hand_misses: left_hand.miss,
-                                    left_hand_bad_cut: left_hand.bad_cut,
-                                    right_hand_hits: right_hand.hit,
-                                    right_hand_misses: right_hand.miss,
-                                    right_hand_bad_cut: right_hand.bad_cut,
+                                Score::from_rts(u, rts).map_err(|e| {
+                                    warn!("{}", e);
+                                    e
                                 })
                             })
                             .collect::<anyhow::Result<Vec<Score>>>()?,
@@ -535,3 +530,22 @@ impl TAState {
         })
     }
 }
+
+fn user_gql(u: &models::User) -> User {
+    User {
+        guid: parse_uuid(&u.guid),
+        name: u.name.clone(),
+        user_id: u.user_id.clone(),
+        play_state: unsafe { std::mem::transmute_copy::<i32, PlayState>(&u.play_state) },
+        download_state: unsafe {
+            std::mem::transmute_copy::<i32, DownloadState>(&u.download_state)
+        },
+        team: u.team.as_ref().map(|t| Team {
+            guid: parse_uuid(&t.id),
+            name: t.name.clone(),
+        }),
+        mod_list: u.mod_list.clone(),
+        stream_delay_ms: u.stream_delay_ms as i32,
+        stream_sync_start_ms: u.stream_sync_start_ms as i32,
+    }
+}
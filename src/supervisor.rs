@@ -0,0 +1,35 @@
+use std::future::Future;
+
+use tracing::{debug, error};
+
+use crate::context::Ctx;
+
+/// Runs `task` until it returns, starting it again if it panics before shutdown.
+pub async fn supervise<F, Fut>(ctx: Ctx, name: &'static str, task: F)
+where
+    F: Fn(Ctx) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    loop {
+        match tokio::spawn(task(ctx.clone())).await {
+            Ok(()) => {
+                debug!("Task {} stopped.", name);
+                return;
+            }
+            Err(e) if e.is_panic() && !ctx.shutdown.is_triggered() => {
+                error!("Task {} panicked, restarting it.", name);
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+            Err(e) if e.is_panic() => {
+                error!("Task {} failed during shutdown.", name);
+                debug!("Error: {}", e);
+                return;
+            }
+            // only the runtime shutting down cancels a supervised task
+            Err(_) => {
+                debug!("Task {} was cancelled.", name);
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+use tars::{
+    config::{Association, AssociationMode, AssociationRule},
+    proto::{models, packet::event},
+};
+
+#[tokio::test]
+async fn rules_only_add_relay_users_to_matching_matches() {
+    let mock = MockTa::start(tournament()).await;
+    let mut config = config(&mock.uri);
+    config.association = Association {
+        mode: AssociationMode::Rules,
+        rules: vec![AssociationRule {
+            users: vec![config.rx_name.clone()],
+            matches_with: vec!["Carol".to_string()],
+        }],
+    };
+    let ctx = relay_with(config).await;
+    let relay_guid = ctx.ta_state.read().await.server_users[0].guid.clone();
+
+    let with_carol = "6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a11";
+    let without_carol = "6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a12";
+    for (guid, users) in [
+        (with_carol, vec![PLAYER_1, COORDINATOR]),
+        (without_carol, vec![PLAYER_2]),
+    ] {
+        mock.event(event::ChangedObject::MatchCreatedEvent(
+            event::MatchCreatedEvent {
+                r#match: Some(models::Match {
+                    guid: guid.to_string(),
+                    associated_users: users.into_iter().map(String::from).collect(),
+                    ..Default::default()
+                }),
+            },
+        ));
+    }
+    eventually(&ctx, |state| state.matches.len() == 3).await;
+
+    let state = ctx.ta_state.read().await;
+    let relay_in = |guid: &str| {
+        state
+            .matches
+            .iter()
+            .any(|m| m.guid == guid && m.associated_users.contains(&relay_guid))
+    };
+    assert!(relay_in(with_carol));
+    assert!(!relay_in(without_carol));
+}
+
+#[tokio::test]
+async fn relay_users_can_be_set_per_match() {
+    let mock = MockTa::start(tournament()).await;
+    let mut config = config(&mock.uri);
+    config.association.mode = AssociationMode::None;
+    let ctx = relay_with(config).await;
+    let relay_guid = ctx.ta_state.read().await.server_users[0].guid.clone();
+
+    let data = query(
+        &ctx,
+        &format!(
+            r#"mutation {{ setMatchRelayUsers(matchId: "{}", users: ["{}"]) {{ relayUsers {{ name }} }} }}"#,
+            MATCH, relay_guid
+        ),
+    )
+    .await;
+    assert_eq!(
+        data,
+        json!({ "setMatchRelayUsers": { "relayUsers": [{ "name": "TA-Relay-RX" }] } })
+    );
+
+    let data = query(
+        &ctx,
+        &format!(
+            r#"mutation {{ setMatchRelayUsers(matchId: "{}", users: []) {{ relayUsers {{ name }} players {{ name }} }} }}"#,
+            MATCH
+        ),
+    )
+    .await;
+    assert_eq!(
+        data,
+        json!({
+            "setMatchRelayUsers": {
+                "relayUsers": [],
+                "players": [{ "name": "Alice" }, { "name": "Bob" }],
+            },
+        })
+    );
+}
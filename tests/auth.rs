@@ -0,0 +1,135 @@
+mod common;
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use common::*;
+
+#[tokio::test]
+async fn upgrade_headers_need_a_key_outside_graphql_ws() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay_with_keys(&mock, keys()).await;
+
+    for path in ["/metrics", "/api/v1/state", "/events"] {
+        let (head, _) = http_get(ctx.config.port, path, "Upgrade: x\r\n").await;
+        assert!(head.starts_with("HTTP/1.1 401"), "{}: {}", path, head);
+    }
+    // only the middleware checks the key, the websocket routes don't again
+    for path in ["/metrics", "/ws/json", "/ws/protobuf"] {
+        let (head, _) = http_get(
+            ctx.config.port,
+            path,
+            "Connection: upgrade\r\nUpgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n",
+        )
+        .await;
+        assert!(head.starts_with("HTTP/1.1 401"), "{}: {}", path, head);
+    }
+}
+
+/// Posts `query` to /graphql with `key`, if any.
+async fn graphql(port: u16, key: Option<&str>, query: &str) -> (String, serde_json::Value) {
+    let body = serde_json::json!({ "query": query }).to_string();
+    let auth = key
+        .map(|k| format!("Authorization: Bearer {}\r\n", k))
+        .unwrap_or_default();
+    let (head, body) = http_request(
+        port,
+        &format!(
+            "POST /graphql HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}\r\n{}",
+            body.len(),
+            auth,
+            body
+        ),
+    )
+    .await;
+    (head, serde_json::from_str(&body).unwrap_or_default())
+}
+
+#[tokio::test]
+async fn roles_decide_what_a_key_can_do() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay_with_keys(&mock, keys()).await;
+    let port = ctx.config.port;
+    let create_pool = r#"mutation { createPool(name: "Finals", maps: []) { name } }"#;
+
+    for key in [None, Some("not-a-key-0123456789")] {
+        let (head, _) = graphql(port, key, "{ servers { name } }").await;
+        assert!(head.starts_with("HTTP/1.1 401"), "{:?}: {}", key, head);
+    }
+
+    let (_, body) = graphql(port, Some("overlay-key-0123456789"), "{ servers { name } }").await;
+    assert_eq!(body["data"]["servers"][0]["name"], "Mock TA");
+    let (_, body) = graphql(
+        port,
+        Some("overlay-key-0123456789"),
+        "{ relayUsers { name } }",
+    )
+    .await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "FORBIDDEN");
+    let (_, body) = graphql(
+        port,
+        Some("producer-key-0123456789"),
+        "{ relayUsers { name } }",
+    )
+    .await;
+    assert!(body["data"]["relayUsers"].is_array(), "{}", body);
+
+    for key in ["overlay-key-0123456789", "producer-key-0123456789"] {
+        let (_, body) = graphql(port, Some(key), create_pool).await;
+        assert_eq!(
+            body["errors"][0]["message"], "This requires the Admin role",
+            "{}",
+            key
+        );
+    }
+    let (_, body) = graphql(port, Some("admin-key-0123456789"), create_pool).await;
+    assert_eq!(body["data"]["createPool"]["name"], "Finals");
+}
+
+/// Collects everything logged through it.
+#[derive(Clone, Default)]
+struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn query_tokens_only_work_on_streams_and_are_not_logged() {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let _logging = tracing::subscriber::set_default(
+        tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish(),
+    );
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay_with_keys(&mock, keys()).await;
+    let port = ctx.config.port;
+
+    for path in ["/api/v1/state", "/metrics", "/graphql"] {
+        let (head, _) = http_get(port, &format!("{}?token=admin-key-0123456789", path), "").await;
+        assert!(head.starts_with("HTTP/1.1 401"), "{}: {}", path, head);
+    }
+    let (_, response) = tokio_tungstenite::connect_async(format!(
+        "ws://127.0.0.1:{}/ws/json?token=overlay-key-0123456789",
+        port
+    ))
+    .await
+    .expect("should connect to /ws/json with a query token");
+    assert_eq!(response.status(), 101);
+
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logs.contains("path=/ws/json"), "{}", logs);
+    assert!(!logs.contains("key-0123456789"), "{}", logs);
+}
@@ -0,0 +1,354 @@
+mod common;
+
+use common::*;
+use tars::{
+    bracket::{BracketKind, BracketSide, BracketSlot, BracketState, Observation, Seed, Tournament},
+    proto::{
+        models,
+        packet::{
+            self,
+            push::{self, song_finished::CompletionType},
+        },
+    },
+    TAState,
+};
+use uuid::Uuid;
+
+fn seeds(count: i32) -> Vec<Seed> {
+    (1..=count)
+        .map(|seed| Seed {
+            seed,
+            name: format!("Seed {}", seed),
+            user_id: format!("user{}", seed),
+        })
+        .collect()
+}
+
+fn slot(tournament: &Tournament, side: BracketSide, index: i32, position: usize) -> &BracketSlot {
+    &tournament
+        .rounds
+        .iter()
+        .find(|r| r.side == side && r.index == index)
+        .expect("the round should exist")
+        .slots[position]
+}
+
+fn seed_of(seed: &Option<Seed>) -> Option<i32> {
+    seed.as_ref().map(|s| s.seed)
+}
+
+/// Decides a best-of-one slot.
+fn win(tournament: &mut Tournament, side: BracketSide, index: i32, position: usize, top: bool) {
+    let guid = slot(tournament, side, index, position).guid;
+    tournament
+        .set_score(guid, top as i32, !top as i32)
+        .expect("the slot should be playable");
+}
+
+/// A double elimination bracket of four, played up to the grand final between
+/// seed 1 (winners bracket) and seed 2 (losers bracket).
+fn to_grand_final() -> Tournament {
+    let mut t = Tournament::new(
+        "Test".to_string(),
+        BracketKind::DoubleElimination,
+        1,
+        seeds(4),
+    )
+    .expect("should create the bracket");
+    win(&mut t, BracketSide::Winners, 0, 0, true); // 1 beats 4
+    win(&mut t, BracketSide::Winners, 0, 1, false); // 3 beats 2
+    win(&mut t, BracketSide::Winners, 1, 0, true); // 1 beats 3
+    win(&mut t, BracketSide::Losers, 0, 0, false); // 2 beats 4
+    win(&mut t, BracketSide::Losers, 1, 0, true); // 2 beats 3
+    t
+}
+
+#[test]
+fn power_of_two_brackets_pair_top_seeds_last() {
+    let t = Tournament::new(
+        "Test".to_string(),
+        BracketKind::SingleElimination,
+        3,
+        seeds(8),
+    )
+    .expect("should create the bracket");
+
+    let names = t.rounds.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(names, ["Quarterfinals", "Semifinals", "Final"]);
+    let first = t.rounds[0]
+        .slots
+        .iter()
+        .map(|s| (seed_of(&s.top), seed_of(&s.bottom)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        first,
+        [
+            (Some(1), Some(8)),
+            (Some(4), Some(5)),
+            (Some(2), Some(7)),
+            (Some(3), Some(6)),
+        ]
+    );
+    assert!(t.rounds[0].slots.iter().all(|s| s.winner.is_none()));
+    assert_eq!(
+        t.rounds[0].slots[0].winner_to,
+        Some(t.rounds[1].slots[0].guid)
+    );
+    assert_eq!(
+        t.rounds[0].slots[3].winner_to,
+        Some(t.rounds[1].slots[1].guid)
+    );
+}
+
+#[test]
+fn byes_advance_the_top_seeds() {
+    let t = Tournament::new(
+        "Test".to_string(),
+        BracketKind::SingleElimination,
+        1,
+        seeds(6),
+    )
+    .expect("should create the bracket");
+
+    assert_eq!(t.rounds[0].slots.len(), 4);
+    assert_eq!(seed_of(&t.rounds[0].slots[0].winner), Some(1));
+    assert_eq!(seed_of(&t.rounds[0].slots[2].winner), Some(2));
+    assert!(t.rounds[0].slots[1].winner.is_none());
+    assert!(t.rounds[0].slots[3].winner.is_none());
+
+    let semis = &t.rounds[1].slots;
+    assert_eq!(seed_of(&semis[0].top), Some(1));
+    assert_eq!(seed_of(&semis[1].top), Some(2));
+    assert!(semis[0].bottom.is_none() && semis[0].winner.is_none());
+}
+
+#[test]
+fn losers_drop_into_the_losers_bracket() {
+    let mut t = Tournament::new(
+        "Test".to_string(),
+        BracketKind::DoubleElimination,
+        1,
+        seeds(4),
+    )
+    .expect("should create the bracket");
+    let names = t.rounds.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "Semifinals",
+            "Final",
+            "Losers Round 1",
+            "Losers Final",
+            "Grand Final",
+            "Grand Final Reset",
+        ]
+    );
+
+    win(&mut t, BracketSide::Winners, 0, 0, true);
+    win(&mut t, BracketSide::Winners, 0, 1, false);
+    let losers = slot(&t, BracketSide::Losers, 0, 0);
+    assert_eq!(seed_of(&losers.top), Some(4));
+    assert_eq!(seed_of(&losers.bottom), Some(2));
+
+    win(&mut t, BracketSide::Winners, 1, 0, true);
+    let losers_final = slot(&t, BracketSide::Losers, 1, 0);
+    assert!(losers_final.top.is_none());
+    assert_eq!(seed_of(&losers_final.bottom), Some(3));
+
+    win(&mut t, BracketSide::Losers, 0, 0, false);
+    let losers_final = slot(&t, BracketSide::Losers, 1, 0);
+    assert_eq!(seed_of(&losers_final.top), Some(2));
+}
+
+#[test]
+fn winners_side_takes_the_grand_final_without_a_reset() {
+    let mut t = to_grand_final();
+    let grand_final = slot(&t, BracketSide::GrandFinal, 0, 0);
+    assert_eq!(seed_of(&grand_final.top), Some(1));
+    assert_eq!(seed_of(&grand_final.bottom), Some(2));
+
+    win(&mut t, BracketSide::GrandFinal, 0, 0, true);
+    let reset = slot(&t, BracketSide::GrandFinal, 1, 0);
+    assert_eq!(seed_of(&reset.winner), Some(1));
+    assert!(reset.bottom.is_none());
+}
+
+#[test]
+fn losers_side_forces_a_grand_final_reset() {
+    let mut t = to_grand_final();
+    win(&mut t, BracketSide::GrandFinal, 0, 0, false);
+
+    let reset = slot(&t, BracketSide::GrandFinal, 1, 0);
+    assert_eq!(seed_of(&reset.top), Some(1));
+    assert_eq!(seed_of(&reset.bottom), Some(2));
+    assert!(reset.winner.is_none());
+
+    win(&mut t, BracketSide::GrandFinal, 1, 0, false);
+    assert_eq!(
+        seed_of(&slot(&t, BracketSide::GrandFinal, 1, 0).winner),
+        Some(2)
+    );
+}
+
+fn song_finished(
+    state: &TAState,
+    player: &str,
+    score: i32,
+    completion: CompletionType,
+) -> Option<Observation> {
+    let player = state
+        .players
+        .iter()
+        .find(|p| p.guid == player)
+        .cloned()
+        .expect("the player should be in the state");
+    Observation::of(
+        state,
+        &packet::Packet {
+            packet: Some(packet::packet::Packet::Push(packet::Push {
+                data: Some(push::Data::SongFinished(push::SongFinished {
+                    player: Some(player),
+                    r#type: completion.into(),
+                    score,
+                    ..Default::default()
+                })),
+            })),
+            ..Default::default()
+        },
+    )
+}
+
+/// Alice and Bob in a best of three, linked to the tournament's match.
+fn linked_set() -> (TAState, BracketState) {
+    let mut state = TAState::new();
+    state.players = vec![
+        user(PLAYER_1, "Alice", models::user::ClientTypes::Player),
+        user(PLAYER_2, "Bob", models::user::ClientTypes::Player),
+    ];
+    state.matches = tournament().matches;
+    let seeds = state
+        .players
+        .iter()
+        .enumerate()
+        .map(|(i, p)| Seed {
+            seed: i as i32 + 1,
+            name: p.name.clone(),
+            user_id: p.user_id.clone(),
+        })
+        .collect();
+
+    let mut bracket = BracketState::new();
+    bracket.tournament = Some(
+        Tournament::new("Test".to_string(), BracketKind::SingleElimination, 3, seeds)
+            .expect("should create the bracket"),
+    );
+    assert!(bracket.observe(Observation::Match {
+        guid: Uuid::parse_str(MATCH).unwrap(),
+        user_ids: state.players.iter().map(|p| p.user_id.clone()).collect(),
+    }));
+    (state, bracket)
+}
+
+/// A player's score on a map, and how they finished it.
+type Finish = (i32, CompletionType);
+
+/// Plays maps with Alice's and Bob's finishes.
+fn play(state: &TAState, bracket: &mut BracketState, maps: &[(Finish, Finish)]) -> BracketSlot {
+    for (alice, bob) in maps {
+        for (player, (score, completion)) in [(PLAYER_1, alice), (PLAYER_2, bob)] {
+            let observation =
+                song_finished(state, player, *score, *completion).expect("should observe the map");
+            assert!(bracket.observe(observation));
+        }
+    }
+    bracket.tournament.as_ref().unwrap().rounds[0].slots[0].clone()
+}
+
+#[test]
+fn finished_songs_decide_the_linked_set() {
+    let (mut state, mut bracket) = linked_set();
+    // the final score comes from SongFinished, not the last realtime score
+    state
+        .rts
+        .insert(PLAYER_2.to_string(), realtime_score(PLAYER_2, 5000, 0.9));
+
+    // Alice wins two maps, so the best of three is hers
+    let passed = CompletionType::Passed;
+    let final_ = play(
+        &state,
+        &mut bracket,
+        &[
+            ((1000, passed), (900, passed)),
+            ((1000, passed), (1100, passed)),
+            ((1200, passed), (1100, passed)),
+        ],
+    );
+    assert_eq!(final_.match_guid, Uuid::parse_str(MATCH).ok());
+    assert_eq!((final_.top_wins, final_.bottom_wins), (2, 1));
+    assert_eq!(
+        final_.winner.as_ref().map(|s| s.name.as_str()),
+        Some("Alice")
+    );
+}
+
+#[test]
+fn failed_maps_lose_to_passes() {
+    let (state, mut bracket) = linked_set();
+    let (passed, failed, quit) = (
+        CompletionType::Passed,
+        CompletionType::Failed,
+        CompletionType::Quit,
+    );
+
+    // Alice fails with the higher score, then quits, so Bob takes both maps
+    let final_ = play(
+        &state,
+        &mut bracket,
+        &[((1200, failed), (900, passed)), ((0, quit), (800, passed))],
+    );
+    assert_eq!((final_.top_wins, final_.bottom_wins), (0, 2));
+    assert_eq!(final_.winner.as_ref().map(|s| s.name.as_str()), Some("Bob"));
+
+    // when both fail, the score still decides the map
+    let (state, mut bracket) = linked_set();
+    let final_ = play(&state, &mut bracket, &[((700, failed), (600, failed))]);
+    assert_eq!((final_.top_wins, final_.bottom_wins), (1, 0));
+}
+
+#[tokio::test]
+async fn seed_files_must_be_in_the_data_directory() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay(&mock).await;
+    let data_dir = &ctx.config.data_dir;
+    std::fs::create_dir_all(data_dir).expect("should create the data directory");
+    let seeds = "seed,name,user_id\n1,Alice,alice\n2,Bob,bob\n";
+    std::fs::write(data_dir.join("seeds.csv"), seeds).expect("should write the seeds");
+    let outside = std::env::temp_dir().join(format!("tars-seeds-{}.csv", Uuid::new_v4()));
+    std::fs::write(&outside, seeds).expect("should write the seeds");
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&outside, data_dir.join("linked.csv"))
+        .expect("should link the seeds");
+
+    let create = |file: &str| {
+        format!(
+            r#"mutation {{ createBracket(name: "Cup", kind: SINGLE_ELIMINATION, bestOf: 3, seedFile: {:?}) {{ name }} }}"#,
+            file
+        )
+    };
+    let data = query(&ctx, &create("seeds.csv")).await;
+    assert_eq!(data["createBracket"]["name"], "Cup");
+
+    let outside_name = format!("../{}", outside.file_name().unwrap().to_string_lossy());
+    let mut refused = vec![outside.to_string_lossy().into_owned(), outside_name];
+    if cfg!(unix) {
+        refused.push("linked.csv".to_string());
+    }
+    for file in refused {
+        let response = query_as(&ctx, Some(tars::auth::Role::Admin), &create(&file)).await;
+        assert_eq!(
+            response.errors[0].message, "Seed file must be inside the data directory.",
+            "{}",
+            file
+        );
+    }
+}
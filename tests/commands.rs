@@ -0,0 +1,192 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+use tars::proto::{
+    models,
+    packet::{self, command, request},
+};
+
+/// Waits up to 5 seconds for the mock to receive a packet forwarded to players.
+async fn forwarded(mock: &MockTa) -> packet::ForwardingPacket {
+    let waiting = async {
+        loop {
+            let forwarded = mock
+                .received()
+                .await
+                .into_iter()
+                .find_map(|p| match p.packet {
+                    Some(packet::packet::Packet::ForwardingPacket(f)) => Some(*f),
+                    _ => None,
+                });
+            if let Some(forwarded) = forwarded {
+                return forwarded;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(5), waiting)
+        .await
+        .expect("the mock should have received a forwarded packet")
+}
+
+#[tokio::test]
+async fn play_song_is_forwarded_to_the_matchs_players() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay(&mock).await;
+
+    let data = query(
+        &ctx,
+        &format!(
+            r#"mutation {{ playSong(matchId: "{}", song: {{ levelId: "custom_level_ABC", difficulty: 4, modifiers: [NO_FAIL, FAST_SONG], disablePause: true }}) }}"#,
+            MATCH
+        ),
+    )
+    .await;
+    assert_eq!(data, json!({ "playSong": true }));
+
+    let forwarded = forwarded(&mock).await;
+    assert_eq!(forwarded.forward_to, vec![PLAYER_1, PLAYER_2]);
+    let play_song = match forwarded.packet.and_then(|p| p.packet) {
+        Some(packet::packet::Packet::Command(packet::Command {
+            r#type: Some(command::Type::PlaySong(play_song)),
+        })) => play_song,
+        other => panic!("expected a PlaySong command, got {:?}", other),
+    };
+    assert!(play_song.disable_pause);
+    let parameters = play_song.gameplay_parameters.unwrap_or_default();
+    let beatmap = parameters.beatmap.unwrap_or_default();
+    assert_eq!(beatmap.level_id, "custom_level_ABC");
+    assert_eq!(beatmap.difficulty, 4);
+    assert_eq!(
+        beatmap.characteristic.unwrap_or_default().serialized_name,
+        "Standard"
+    );
+    use models::gameplay_modifiers::GameOptions;
+    assert_eq!(
+        parameters.gameplay_modifiers.unwrap_or_default().options,
+        GameOptions::NoFail as i32 | GameOptions::FastSong as i32
+    );
+}
+
+#[tokio::test]
+async fn show_modal_returns_its_id() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay(&mock).await;
+
+    let data = query(
+        &ctx,
+        &format!(
+            r#"mutation {{ showModal(matchId: "{}", title: "Ready?", text: "Next song is up", options: [{{ label: "Yes", value: "yes" }}]) }}"#,
+            MATCH
+        ),
+    )
+    .await;
+
+    let forwarded = forwarded(&mock).await;
+    let modal = match forwarded.packet.and_then(|p| p.packet) {
+        Some(packet::packet::Packet::Request(packet::Request {
+            r#type: Some(request::Type::ShowModal(modal)),
+        })) => modal,
+        other => panic!("expected a ShowModal request, got {:?}", other),
+    };
+    assert_eq!(data, json!({ "showModal": modal.modal_id }));
+    assert_eq!(modal.option1.map(|o| o.value), Some("yes".to_string()));
+    assert_eq!(modal.option2, None);
+}
+
+#[tokio::test]
+async fn match_changes_return_once_ta_confirms_them() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay(&mock).await;
+
+    let data = query(
+        &ctx,
+        &format!(
+            r#"mutation {{ createMatch(players: ["{}"], coordinators: ["{}"]) {{ guid players {{ name }} coordinators {{ name }} }} }}"#,
+            PLAYER_2, COORDINATOR
+        ),
+    )
+    .await;
+    let created = &data["createMatch"];
+    assert_eq!(created["players"], json!([{ "name": "Bob" }]));
+    assert_eq!(created["coordinators"], json!([{ "name": "Carol" }]));
+    let guid = created["guid"].as_str().expect("should have a guid");
+
+    let data = query(
+        &ctx,
+        &format!(
+            r#"mutation {{ addUsersToMatch(matchId: "{}", users: ["{}"]) {{ players {{ name }} }} }}"#,
+            guid, PLAYER_1
+        ),
+    )
+    .await;
+    assert_eq!(
+        data["addUsersToMatch"]["players"],
+        json!([{ "name": "Bob" }, { "name": "Alice" }])
+    );
+
+    query(
+        &ctx,
+        &format!(
+            r#"mutation {{ setMatchLevel(matchId: "{}", level: {{ levelId: "custom_level_ABC", name: "Lumina", difficulty: 3 }}) {{ guid }} }}"#,
+            guid
+        ),
+    )
+    .await;
+    {
+        let state = ctx.ta_state.read().await;
+        let r#match = state
+            .matches
+            .iter()
+            .find(|m| m.guid == guid)
+            .expect("the match should exist");
+        assert_eq!(
+            r#match.selected_level.as_ref().map(|l| l.level_id.as_str()),
+            Some("custom_level_ABC")
+        );
+        assert_eq!(r#match.selected_difficulty, 3);
+    }
+
+    let data = query(
+        &ctx,
+        &format!(r#"mutation {{ deleteMatch(matchId: "{}") }}"#, guid),
+    )
+    .await;
+    assert_eq!(data, json!({ "deleteMatch": true }));
+    assert!(!ctx
+        .ta_state
+        .read()
+        .await
+        .matches
+        .iter()
+        .any(|m| m.guid == guid));
+}
+
+#[tokio::test]
+async fn match_changes_are_confirmed_when_ta_reorders_them() {
+    let mock = MockTa::start(tournament()).await;
+    mock.normalize_matches();
+    let ctx = relay(&mock).await;
+
+    let data = query(
+        &ctx,
+        &format!(
+            r#"mutation {{ removeUsersFromMatch(matchId: "{m}", users: ["{p}"]) {{ guid }} addUsersToMatch(matchId: "{m}", users: ["{p}"]) {{ guid }} }}"#,
+            m = MATCH,
+            p = PLAYER_2
+        ),
+    )
+    .await;
+    assert_eq!(data["addUsersToMatch"]["guid"], MATCH);
+
+    let data = query(
+        &ctx,
+        &format!(
+            r#"mutation {{ setMatchLevel(matchId: "{}", level: {{ levelId: "custom_level_ABC", name: "Lumina", difficulty: 3 }}) {{ guid }} }}"#,
+            MATCH
+        ),
+    )
+    .await;
+    assert_eq!(data["setMatchLevel"]["guid"], MATCH);
+}
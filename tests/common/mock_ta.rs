@@ -0,0 +1,192 @@
+//! An in-process stand-in for a TournamentAssistant server.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use prost::Message as _;
+use tars::proto::{
+    models,
+    packet::{self, event, push},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, Mutex},
+};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Answers every `Connect` request with a scripted `State`, then forwards
+/// whatever the test pushes, and the events clients send, to every connected client.
+pub struct MockTa {
+    pub uri: String,
+    outgoing: broadcast::Sender<packet::Packet>,
+    received: Arc<Mutex<Vec<packet::Packet>>>,
+    normalize: Arc<AtomicBool>,
+}
+
+impl MockTa {
+    /// Starts listening on a free local port.
+    pub async fn start(state: models::State) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("should bind a local port");
+        let uri = format!(
+            "ws://{}",
+            listener.local_addr().expect("should have an address")
+        );
+        let (outgoing, _) = broadcast::channel(256);
+        let received = Arc::new(Mutex::new(vec![]));
+        let normalize = Arc::new(AtomicBool::new(false));
+
+        let mock = Self {
+            uri,
+            outgoing: outgoing.clone(),
+            received: received.clone(),
+            normalize: normalize.clone(),
+        };
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(
+                    stream,
+                    state.clone(),
+                    outgoing.clone(),
+                    received.clone(),
+                    normalize.clone(),
+                ));
+            }
+        });
+        mock
+    }
+
+    /// Sends a packet to every connected client.
+    pub fn send(&self, packet: packet::packet::Packet) {
+        let _ = self.outgoing.send(packet::Packet {
+            id: uuid::Uuid::new_v4().to_string(),
+            from: uuid::Uuid::nil().to_string(),
+            packet: Some(packet),
+        });
+    }
+
+    pub fn event(&self, changed_object: event::ChangedObject) {
+        self.send(packet::packet::Packet::Event(packet::Event {
+            changed_object: Some(changed_object),
+        }));
+    }
+
+    pub fn realtime_score(&self, score: models::RealtimeScore) {
+        self.send(packet::packet::Packet::Push(packet::Push {
+            data: Some(push::Data::RealtimeScore(score)),
+        }));
+    }
+
+    pub fn song_finished(&self, finished: push::SongFinished) {
+        self.send(packet::packet::Packet::Push(packet::Push {
+            data: Some(push::Data::SongFinished(finished)),
+        }));
+    }
+
+    /// Echoes matches the way TA may store them rather than byte for byte: users
+    /// in a different order and the selected level marked as loaded.
+    pub fn normalize_matches(&self) {
+        self.normalize.store(true, Ordering::SeqCst);
+    }
+
+    /// Every packet clients have sent, `Connect` requests included.
+    pub async fn received(&self) -> Vec<packet::Packet> {
+        self.received.lock().await.clone()
+    }
+}
+
+async fn serve(
+    stream: tokio::net::TcpStream,
+    mut state: models::State,
+    broadcast: broadcast::Sender<packet::Packet>,
+    received: Arc<Mutex<Vec<packet::Packet>>>,
+    normalize: Arc<AtomicBool>,
+) {
+    let mut outgoing = broadcast.subscribe();
+    let Ok(ws) = tokio_tungstenite::accept_async(stream).await else {
+        return;
+    };
+    let (mut tx, mut rx) = ws.split();
+
+    loop {
+        tokio::select! {
+            msg = rx.next() => {
+                let data = match msg {
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => return,
+                    Some(Ok(_)) => continue,
+                };
+                let Ok(packet) = packet::Packet::decode(data.as_slice()) else {
+                    continue;
+                };
+                received.lock().await.push(packet.clone());
+
+                // TA tells everyone about a client's change to users or matches
+                if let Some(packet::packet::Packet::Event(event)) = &packet.packet {
+                    let mut event = event.clone();
+                    if normalize.load(Ordering::SeqCst) {
+                        normalize_match(&mut event);
+                    }
+                    let _ = broadcast.send(packet::Packet {
+                        packet: Some(packet::packet::Packet::Event(event)),
+                        ..packet.clone()
+                    });
+                }
+
+                if let Some(packet::packet::Packet::Request(packet::Request {
+                    r#type: Some(packet::request::Type::Connect(connect)),
+                })) = packet.packet
+                {
+                    state.users.extend(connect.user);
+                    let response = packet::Packet {
+                        id: uuid::Uuid::new_v4().to_string(),
+                        from: uuid::Uuid::nil().to_string(),
+                        packet: Some(packet::packet::Packet::Response(packet::Response {
+                            r#type: packet::response::ResponseType::Success.into(),
+                            responding_to_packet_id: packet.id,
+                            details: Some(packet::response::Details::Connect(
+                                packet::response::Connect {
+                                    state: Some(state.clone()),
+                                    server_version: connect.client_version,
+                                    message: "Connected".to_string(),
+                                    ..Default::default()
+                                },
+                            )),
+                        })),
+                    };
+                    if tx.send(Message::Binary(response.encode_to_vec())).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            packet = outgoing.recv() => {
+                let Ok(packet) = packet else {
+                    return;
+                };
+                if tx.send(Message::Binary(packet.encode_to_vec())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn normalize_match(event: &mut packet::Event) {
+    let r#match = match &mut event.changed_object {
+        Some(event::ChangedObject::MatchCreatedEvent(event::MatchCreatedEvent { r#match }))
+        | Some(event::ChangedObject::MatchUpdatedEvent(event::MatchUpdatedEvent { r#match })) => {
+            r#match
+        }
+        _ => return,
+    };
+    if let Some(r#match) = r#match {
+        r#match.associated_users.reverse();
+        if let Some(level) = &mut r#match.selected_level {
+            level.loaded = true;
+        }
+    }
+}
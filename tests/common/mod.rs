@@ -0,0 +1,276 @@
+#![allow(dead_code)]
+
+pub mod mock_ta;
+
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
+
+use tars::{
+    auth::{Keys, Role},
+    config::{Config, Features},
+    http, ingest, outbound,
+    proto::models,
+    schema_builder, AppContext, Ctx, TAConnection, TAState,
+};
+
+pub use mock_ta::MockTa;
+
+pub const PLAYER_1: &str = "6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a01";
+pub const PLAYER_2: &str = "6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a02";
+pub const COORDINATOR: &str = "6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a03";
+pub const MATCH: &str = "6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a10";
+
+pub fn user(guid: &str, name: &str, client_type: models::user::ClientTypes) -> models::User {
+    models::User {
+        guid: guid.to_string(),
+        name: name.to_string(),
+        user_id: format!("7656119{}", &guid[guid.len() - 2..]),
+        client_type: client_type.into(),
+        ..Default::default()
+    }
+}
+
+/// Two players and a coordinator, all in one match.
+pub fn tournament() -> models::State {
+    models::State {
+        server_settings: Some(models::ServerSettings {
+            server_name: "Mock TA".to_string(),
+            score_update_frequency: 30,
+            ..Default::default()
+        }),
+        users: vec![
+            user(PLAYER_1, "Alice", models::user::ClientTypes::Player),
+            user(PLAYER_2, "Bob", models::user::ClientTypes::Player),
+            user(COORDINATOR, "Carol", models::user::ClientTypes::Coordinator),
+        ],
+        matches: vec![models::Match {
+            guid: MATCH.to_string(),
+            associated_users: vec![
+                PLAYER_1.to_string(),
+                PLAYER_2.to_string(),
+                COORDINATOR.to_string(),
+            ],
+            leader: COORDINATOR.to_string(),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+pub fn realtime_score(user_guid: &str, score: i32, accuracy: f32) -> models::RealtimeScore {
+    models::RealtimeScore {
+        user_guid: user_guid.to_string(),
+        score,
+        score_with_modifiers: score,
+        accuracy,
+        combo: 42,
+        left_hand: Some(models::ScoreTrackerHand::default()),
+        right_hand: Some(models::ScoreTrackerHand::default()),
+        ..Default::default()
+    }
+}
+
+/// A config for a relay of `uri` that keeps its files in a fresh temporary directory.
+pub fn config(uri: &str) -> Config {
+    Config {
+        ta_uri: uri.to_string(),
+        data_dir: std::env::temp_dir().join(format!("tars-test-{}", uuid::Uuid::new_v4())),
+        features: Features {
+            playground: false,
+            record_rts: false,
+        },
+        ..Default::default()
+    }
+}
+
+/// Connects a relay to `mock` and waits for the `Connect` response to be routed.
+pub async fn relay(mock: &MockTa) -> Ctx {
+    relay_with(config(&mock.uri)).await
+}
+
+pub async fn relay_with(config: Config) -> Ctx {
+    let ctx = AppContext::new(config);
+    let con = TAConnection::connect(&ctx.config.ta_uri, &ctx.config.rx_name, "")
+        .await
+        .expect("should connect to the mock server");
+    tokio::spawn(ingest::run(ctx.clone(), Some(con)));
+    tokio::spawn(outbound::run(ctx.clone()));
+
+    eventually(&ctx, |state| state.server_settings.is_some()).await;
+    ctx
+}
+
+/// Connects a relay to `mock` and serves its HTTP API, without keys, on a free
+/// local port.
+pub async fn relay_with_http(mock: &MockTa) -> Ctx {
+    relay_with_keys(mock, Keys::disabled()).await
+}
+
+/// Like [`relay_with_http`], with the API behind `keys`.
+pub async fn relay_with_keys(mock: &MockTa, keys: Keys) -> Ctx {
+    let ctx = relay_with(http_config(&mock.uri)).await;
+    serve_http(&ctx, keys).await;
+    ctx
+}
+
+/// A test config listening on a free local port.
+pub fn http_config(uri: &str) -> Config {
+    let mut config = config(uri);
+    config.bind = "127.0.0.1".to_string();
+    config.port = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.local_addr())
+        .expect("should find a free port")
+        .port();
+    config
+}
+
+/// Serves the HTTP API behind `keys` and waits for it to listen.
+pub async fn serve_http(ctx: &Ctx, keys: Keys) {
+    tokio::spawn(http::serve(ctx.clone(), keys));
+    listening(ctx).await;
+}
+
+/// Waits up to 5 seconds for the relay's HTTP server to listen.
+pub async fn listening(ctx: &Ctx) {
+    let listening = async {
+        while tokio::net::TcpStream::connect(("127.0.0.1", ctx.config.port))
+            .await
+            .is_err()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), listening)
+        .await
+        .expect("the HTTP server should be listening");
+}
+
+/// An overlay, a producer and an admin key, named after their roles.
+pub fn keys() -> Keys {
+    let path = std::env::temp_dir().join(format!("tars-keys-{}.toml", uuid::Uuid::new_v4()));
+    std::fs::write(
+        &path,
+        r#"
+            [[keys]]
+            name = "overlay"
+            key = "overlay-key-0123456789"
+            role = "overlay"
+
+            [[keys]]
+            name = "producer"
+            key = "producer-key-0123456789"
+            role = "producer"
+
+            [[keys]]
+            name = "admin"
+            key = "admin-key-0123456789"
+            role = "admin"
+        "#,
+    )
+    .expect("should write the keys file");
+    Keys::load(&path).expect("should load the keys file")
+}
+
+/// Sends a raw HTTP/1.1 request to the relay, returning the response's status
+/// line and headers, and its body.
+pub async fn http_request(port: u16, request: &str) -> (String, String) {
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .expect("should connect to the relay");
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .expect("should send the request");
+
+    let mut response = String::new();
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_string(&mut response))
+        .await
+        .expect("should respond")
+        .expect("should read the response");
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .expect("should have headers");
+    (head.to_owned(), body.to_owned())
+}
+
+/// Sends a GET request with extra `headers`, each ending in `\r\n`.
+pub async fn http_get(port: u16, path: &str, headers: &str) -> (String, String) {
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{}\r\n",
+        path, headers
+    );
+    http_request(port, &request).await
+}
+
+/// Waits up to 5 seconds for the primary server's state to satisfy `check`.
+pub async fn eventually(ctx: &Ctx, check: impl Fn(&TAState) -> bool) {
+    let waiting = async {
+        while !check(&*ctx.ta_state.read().await) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), waiting)
+        .await
+        .expect("the relay's state should have changed");
+}
+
+/// Runs `query` as an admin, failing on any error.
+pub async fn query(ctx: &Ctx, query: &str) -> serde_json::Value {
+    let response = query_as(ctx, Some(Role::Admin), query).await;
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    response.data.into_json().expect("should be valid json")
+}
+
+/// Runs `query` as `role`, or without one, errors and all.
+pub async fn query_as(ctx: &Ctx, role: Option<Role>, query: &str) -> async_graphql::Response {
+    let mut request = async_graphql::Request::new(query);
+    if let Some(role) = role {
+        request = request.data(role);
+    }
+    schema_builder(ctx).finish().execute(request).await
+}
+
+pub type Ws =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Sends a JSON message over a websocket.
+pub async fn ws_send(ws: &mut Ws, message: serde_json::Value) {
+    ws.send(Message::Text(message.to_string()))
+        .await
+        .expect("should send the message");
+}
+
+/// Waits up to 5 seconds for the next JSON message on a websocket.
+pub async fn ws_next(ws: &mut Ws) -> serde_json::Value {
+    match tokio::time::timeout(Duration::from_secs(5), ws.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str(&text).expect("should be JSON"),
+        other => panic!("expected a JSON message, got {:?}", other),
+    }
+}
+
+/// Opens a graphql-transport-ws connection to the relay, authenticated with `token`
+/// in `connection_init`.
+pub async fn graphql_ws(port: u16, token: &str) -> Ws {
+    let mut request = format!("ws://127.0.0.1:{}/graphql/ws", port)
+        .into_client_request()
+        .expect("should be a websocket request");
+    request.headers_mut().insert(
+        "Sec-WebSocket-Protocol",
+        "graphql-transport-ws"
+            .parse()
+            .expect("should be a header value"),
+    );
+    let (mut ws, _) = tokio_tungstenite::connect_async(request)
+        .await
+        .expect("should connect to /graphql/ws");
+    ws_send(
+        &mut ws,
+        serde_json::json!({ "type": "connection_init", "payload": { "token": token } }),
+    )
+    .await;
+    assert_eq!(ws_next(&mut ws).await["type"], "connection_ack");
+    ws
+}
@@ -0,0 +1,101 @@
+use poem::{
+    endpoint::make_sync,
+    http::{header, Method, StatusCode},
+    Endpoint, EndpointExt, Request,
+};
+use tars::config::{command, Config, Cors};
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("tars-test-{}-{}", uuid::Uuid::new_v4(), name))
+}
+
+#[test]
+fn arguments_beat_the_environment_which_beats_the_file() {
+    let file = temp_path("config.toml");
+    std::fs::write(
+        &file,
+        "bind = \"127.0.0.1\"\nport = 1000\nrx_name = \"File RX\"\ntx_name = \"File TX\"\n",
+    )
+    .expect("should write the config file");
+    std::env::set_var("TARS_PORT", "2000");
+    std::env::set_var("TARS_RX_NAME", "Env RX");
+
+    let args = command()
+        .try_get_matches_from([
+            "tars",
+            "--config",
+            file.to_str().unwrap(),
+            "--rx-name",
+            "Arg RX",
+        ])
+        .expect("should parse the arguments");
+    let config = Config::load(&args).expect("should load the config");
+    std::env::remove_var("TARS_PORT");
+    std::env::remove_var("TARS_RX_NAME");
+
+    assert_eq!(config.bind, "127.0.0.1");
+    assert_eq!(config.tx_name, "File TX");
+    assert_eq!(config.port, 2000);
+    assert_eq!(config.rx_name, "Arg RX");
+}
+
+#[test]
+fn validating_leaves_the_data_directory_alone() {
+    let config = Config {
+        data_dir: temp_path("data"),
+        ..Default::default()
+    };
+
+    config.validate();
+    assert!(!config.data_dir.exists());
+}
+
+#[test]
+fn cors_credentials_need_explicit_origins() {
+    let config = Config {
+        cors: Cors {
+            allow_credentials: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    assert!(config
+        .validate()
+        .contains(&"cors credentials need explicit origins, not \"*\"".to_string()));
+}
+
+#[tokio::test]
+async fn cors_only_answers_listed_origins() {
+    let cors = Cors {
+        origins: vec!["https://*.example.com".to_string()],
+        allow_credentials: true,
+        ..Default::default()
+    };
+    let api = make_sync(|_| "ok").with(cors.middleware());
+    let preflight = |origin: &str| {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .finish()
+    };
+
+    let allowed = api
+        .get_response(preflight("https://overlay.example.com"))
+        .await;
+    assert_eq!(allowed.status(), StatusCode::OK);
+    assert_eq!(
+        allowed.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://overlay.example.com"
+    );
+    assert_eq!(
+        allowed.headers()[header::ACCESS_CONTROL_ALLOW_CREDENTIALS],
+        "true"
+    );
+
+    let refused = api
+        .get_response(preflight("https://example.com.evil.net"))
+        .await;
+    assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::*;
+use futures_util::StreamExt;
+use serde_json::json;
+use tars::{config::ServerConfig, gql, playback::RtsUpdate, AppContext, TAState};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::RwLock,
+};
+
+#[tokio::test]
+async fn json_websocket_streams_the_state_then_filtered_scores() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay_with_http(&mock).await;
+
+    let uri = format!(
+        "ws://127.0.0.1:{}/ws/json?matchId={}&userId={}",
+        ctx.config.port, MATCH, PLAYER_2
+    );
+    let (mut ws, _) = tokio_tungstenite::connect_async(uri)
+        .await
+        .expect("should connect to the feed");
+    let state = ws_next(&mut ws).await;
+    assert_eq!(state["type"], "state");
+    assert_eq!(state["data"]["matches"][0]["guid"], MATCH);
+    assert_eq!(
+        state["data"]["players"],
+        json!([
+            {
+                "guid": PLAYER_1,
+                "name": "Alice",
+                "userId": "765611901",
+                "playState": "WAITING",
+                "downloadState": "NONE",
+                "team": null,
+                "modList": [],
+                "streamDelayMs": 0,
+                "streamSyncStartMs": 0,
+            },
+            {
+                "guid": PLAYER_2,
+                "name": "Bob",
+                "userId": "765611902",
+                "playState": "WAITING",
+                "downloadState": "NONE",
+                "team": null,
+                "modList": [],
+                "streamDelayMs": 0,
+                "streamSyncStartMs": 0,
+            },
+        ])
+    );
+
+    mock.realtime_score(realtime_score(PLAYER_1, 111, 0.9));
+    mock.realtime_score(realtime_score(PLAYER_2, 222, 0.95));
+    let score = ws_next(&mut ws).await;
+    assert_eq!(score["type"], "score");
+    assert_eq!(score["data"]["ownerGuid"], PLAYER_2);
+    assert_eq!(score["data"]["score"], 222);
+}
+
+#[tokio::test]
+async fn events_start_with_the_state() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay_with_http(&mock).await;
+
+    let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", ctx.config.port))
+        .await
+        .expect("should connect to the relay");
+    stream
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\n\r\n")
+        .await
+        .expect("should send the request");
+
+    let reading = async {
+        let mut received = String::new();
+        let mut buf = [0; 4096];
+        while !received.contains("\"name\":\"Alice\"") {
+            let n = stream
+                .read(&mut buf)
+                .await
+                .expect("should read the response");
+            assert!(n > 0, "the stream closed early: {}", received);
+            received.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+        received
+    };
+    let received = tokio::time::timeout(Duration::from_secs(5), reading)
+        .await
+        .expect("should receive the state");
+    assert!(received.starts_with("HTTP/1.1 200"));
+    assert!(received.contains("text/event-stream"));
+    assert!(received.contains("event: state"));
+}
+
+#[tokio::test]
+async fn scores_follow_matches_on_every_server() {
+    let ctx = AppContext::new(config("ws://127.0.0.1:1"));
+    let mut other = TAState::for_server(ServerConfig {
+        uri: "ws://127.0.0.1:2".to_string(),
+        password: String::new(),
+    });
+    other.matches = tournament().matches;
+    ctx.server_states
+        .write()
+        .await
+        .insert(other.server.uri.clone(), Arc::new(RwLock::new(other)));
+
+    let mut scores = gql::score_updates(ctx.clone(), MATCH.parse().ok(), None, false);
+    for player in ["6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a04", PLAYER_2] {
+        ctx.rts_updates.send(RtsUpdate {
+            user_guid: player.to_string(),
+            user_name: String::new(),
+            received_at_ms: 0,
+            score: realtime_score(player, 222, 0.95),
+        });
+    }
+    let score = tokio::time::timeout(Duration::from_secs(5), scores.next())
+        .await
+        .expect("should receive a score")
+        .expect("the stream should stay open");
+    assert_eq!(score.user_guid, PLAYER_2);
+}
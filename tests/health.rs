@@ -0,0 +1,40 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use tars::{ingest, AppContext, TAConnection};
+
+#[tokio::test]
+async fn ready_only_once_ta_has_sent_its_state() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = AppContext::new(http_config(&mock.uri));
+    serve_http(&ctx, keys()).await;
+
+    let (head, body) = http_get(ctx.config.port, "/readyz", "").await;
+    assert!(head.starts_with("HTTP/1.1 503"), "{}", head);
+    assert!(body.contains(r#""status":"not ready""#), "{}", body);
+    assert!(body.contains(r#""server":null"#), "{}", body);
+    let (head, _) = http_get(ctx.config.port, "/healthz", "").await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+
+    let con = TAConnection::connect(&ctx.config.ta_uri, &ctx.config.rx_name, "")
+        .await
+        .expect("should connect to the mock server");
+    tokio::spawn(ingest::run(ctx.clone(), Some(con)));
+
+    let ready = async {
+        loop {
+            let (head, body) = http_get(ctx.config.port, "/readyz", "").await;
+            if head.starts_with("HTTP/1.1 200") {
+                return body;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    let body = tokio::time::timeout(Duration::from_secs(5), ready)
+        .await
+        .expect("the relay should become ready");
+    assert!(body.contains(r#""status":"ok""#), "{}", body);
+    assert!(body.contains(r#""server":"Mock TA""#), "{}", body);
+}
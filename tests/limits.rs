@@ -0,0 +1,70 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+use tars::auth::Keys;
+
+/// A relay allowing `per_minute` requests per client, serving its API behind `keys`.
+async fn relay_limited(mock: &MockTa, per_minute: u32, keys: Keys) -> tars::Ctx {
+    let mut config = http_config(&mock.uri);
+    config.limits.requests_per_minute = per_minute;
+    config.limits.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+    let ctx = relay_with(config).await;
+    serve_http(&ctx, keys).await;
+    ctx
+}
+
+#[tokio::test]
+async fn each_websocket_operation_is_rate_limited() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay_limited(&mock, 3, keys()).await;
+
+    let mut ws = graphql_ws(ctx.config.port, "overlay-key-0123456789").await;
+
+    // the key's bucket, not the upgrade's, pays for the operations
+    for id in ["1", "2", "3"] {
+        ws_send(
+            &mut ws,
+            json!({ "id": id, "type": "subscribe", "payload": { "query": "{ server { name } }" } }),
+        )
+        .await;
+        let response = ws_next(&mut ws).await;
+        assert_eq!(
+            response["payload"]["data"],
+            json!({ "server": { "name": "Mock TA" } })
+        );
+        assert_eq!(ws_next(&mut ws).await["type"], "complete");
+    }
+
+    ws_send(
+        &mut ws,
+        json!({ "id": "4", "type": "subscribe", "payload": { "query": "{ server { name } }" } }),
+    )
+    .await;
+    let response = ws_next(&mut ws).await;
+    assert_eq!(
+        response["payload"]["errors"][0]["extensions"]["code"],
+        "RATE_LIMITED"
+    );
+}
+
+#[tokio::test]
+async fn clients_behind_a_trusted_proxy_are_told_apart() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay_limited(&mock, 1, Keys::disabled()).await;
+
+    // the first address is the client's claim, only the one the proxy added counts
+    for (client, status) in [
+        ("198.51.100.1", "200"),
+        ("198.51.100.1", "429"),
+        ("198.51.100.2", "200"),
+    ] {
+        let forwarded = format!("X-Forwarded-For: 203.0.113.9, {}\r\n", client);
+        let (head, _) = http_get(ctx.config.port, "/api/v1/state", &forwarded).await;
+        assert!(
+            head.starts_with(&format!("HTTP/1.1 {}", status)),
+            "{}",
+            head
+        );
+    }
+}
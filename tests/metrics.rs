@@ -0,0 +1,73 @@
+mod common;
+
+use common::*;
+
+/// The value of an unlabelled or fully labelled metric, e.g. `tars_players`.
+fn metric(body: &str, name: &str) -> Option<f64> {
+    body.lines()
+        .filter(|l| !l.starts_with('#'))
+        .find_map(|l| l.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[tokio::test]
+async fn metrics_follow_the_ta_connection() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay_with_keys(&mock, keys()).await;
+
+    let (head, _) = http_get(ctx.config.port, "/metrics", "").await;
+    assert!(head.starts_with("HTTP/1.1 401"), "{}", head);
+
+    let (head, body) = http_get(
+        ctx.config.port,
+        "/metrics",
+        "X-Api-Key: overlay-key-0123456789\r\n",
+    )
+    .await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(metric(&body, "tars_ta_connected"), Some(1.0));
+    let server = |name: &str| format!(r#"{}{{server="{}"}}"#, name, mock.uri);
+    assert_eq!(metric(&body, &server("tars_players")), Some(2.0));
+    assert_eq!(metric(&body, &server("tars_coordinators")), Some(1.0));
+    assert_eq!(metric(&body, &server("tars_matches")), Some(1.0));
+    assert!(
+        metric(&body, r#"tars_packets_received_total{type="response"}"#) >= Some(1.0),
+        "{}",
+        body
+    );
+}
+
+#[tokio::test]
+async fn state_metrics_are_labelled_by_server() {
+    let primary = MockTa::start(tournament()).await;
+    let mut state = tournament();
+    state.matches.clear();
+    let other = MockTa::start(state).await;
+    let mut config = http_config(&primary.uri);
+    config.servers = vec![tars::config::ServerConfig {
+        uri: other.uri.clone(),
+        password: String::new(),
+    }];
+    let ctx = relay_with(config).await;
+    serve_http(&ctx, tars::auth::Keys::disabled()).await;
+
+    let matches =
+        |body: &str, uri: &str| metric(body, &format!(r#"tars_matches{{server="{}"}}"#, uri));
+    let waiting = async {
+        loop {
+            let (_, body) = http_get(ctx.config.port, "/metrics", "").await;
+            if matches(&body, &other.uri).is_some() {
+                return body;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    };
+    let body = tokio::time::timeout(std::time::Duration::from_secs(5), waiting)
+        .await
+        .expect("the other server's metrics should show up");
+    assert_eq!(matches(&body, &primary.uri), Some(1.0));
+    assert_eq!(matches(&body, &other.uri), Some(0.0));
+    assert_eq!(
+        metric(&body, &format!(r#"tars_players{{server="{}"}}"#, other.uri)),
+        Some(2.0)
+    );
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::*;
+use futures_util::StreamExt;
+use tars::{
+    proto::packet::{self, push, response},
+    TAConnection,
+};
+
+async fn next(overlay: &mut TAConnection) -> packet::Packet {
+    tokio::time::timeout(std::time::Duration::from_secs(5), overlay.next())
+        .await
+        .expect("should receive a packet")
+        .expect("the passthrough should stay open")
+        .expect("should be a valid packet")
+}
+
+#[tokio::test]
+async fn late_joiners_get_the_state_then_the_stream() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay_with_http(&mock).await;
+
+    let uri = format!("ws://127.0.0.1:{}/ws/protobuf", ctx.config.port);
+    let mut overlay = TAConnection::connect(&uri, "Legacy overlay", "")
+        .await
+        .expect("should connect to the passthrough");
+    let state = match next(&mut overlay).await.packet {
+        Some(packet::packet::Packet::Response(packet::Response {
+            details: Some(response::Details::Connect(connect)),
+            ..
+        })) => connect.state.unwrap_or_default(),
+        other => panic!("expected a Connect response, got {:?}", other),
+    };
+    assert_eq!(state.matches.len(), 1);
+    assert!(state.users.iter().any(|u| u.name == "Alice"));
+    assert!(state.users.iter().any(|u| u.name == "Legacy overlay"));
+
+    mock.realtime_score(realtime_score(PLAYER_1, 123456, 0.95));
+    match next(&mut overlay).await.packet {
+        Some(packet::packet::Packet::Push(packet::Push {
+            data: Some(push::Data::RealtimeScore(score)),
+        })) => assert_eq!(score.score, 123456),
+        other => panic!("expected a realtime score, got {:?}", other),
+    }
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::*;
+use futures_util::{Stream, StreamExt};
+use tars::{
+    gql::score_updates,
+    playback::{delay_scores, RtsUpdate, StreamDelay},
+    AppContext,
+};
+use tokio::time::{Duration, Instant};
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+fn update(user_guid: &str, score: i32, received_at_ms: i64) -> RtsUpdate {
+    RtsUpdate {
+        user_guid: user_guid.to_string(),
+        user_name: user_guid.to_string(),
+        received_at_ms,
+        score: realtime_score(user_guid, score, 0.9),
+    }
+}
+
+fn delay(delay_ms: i64) -> StreamDelay {
+    StreamDelay {
+        delay_ms,
+        sync_start_ms: 0,
+    }
+}
+
+/// Delays updates sent into the returned sender by each player's delay in `delays`.
+fn delayed(
+    delays: HashMap<&'static str, StreamDelay>,
+) -> (
+    futures::channel::mpsc::UnboundedSender<RtsUpdate>,
+    impl Stream<Item = RtsUpdate>,
+) {
+    let (updates, feed) = futures::channel::mpsc::unbounded();
+    let scores = delay_scores(feed, move |user_guid| {
+        let delay = delays.get(user_guid.as_str()).copied().unwrap_or_default();
+        async move { delay }
+    });
+    (updates, scores)
+}
+
+/// The scores `stream` releases, and how long after `start` each came out.
+async fn released(
+    stream: impl Stream<Item = RtsUpdate>,
+    start: Instant,
+    count: usize,
+) -> Vec<(i32, Duration)> {
+    stream
+        .take(count)
+        .map(|update| (update.score.score, start.elapsed()))
+        .collect()
+        .await
+}
+
+/// Whether `elapsed` is `expected_ms`, give or take the wall clock moving on while
+/// the test runs.
+fn about(elapsed: Duration, expected_ms: u64) -> bool {
+    elapsed.as_millis().abs_diff(expected_ms as u128) <= 50
+}
+
+#[test]
+fn release_is_clamped_to_the_sync_start() {
+    let sync = StreamDelay {
+        delay_ms: 100,
+        sync_start_ms: 10_000,
+    };
+    // before the player's stream started the song, the score waits for it
+    assert_eq!(update(PLAYER_1, 1, 9_000).release_at_ms(sync), 10_100);
+    // after, it's just the delay
+    assert_eq!(update(PLAYER_1, 1, 12_000).release_at_ms(sync), 12_100);
+    // negative delays don't release scores early
+    assert_eq!(
+        update(PLAYER_1, 1, 12_000).release_at_ms(delay(-500)),
+        12_000
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn delays_differ_per_player() {
+    let (updates, scores) = delayed(HashMap::from([
+        (PLAYER_1, delay(300)),
+        (PLAYER_2, delay(100)),
+    ]));
+    let start = Instant::now();
+    let now = now_ms();
+    updates.unbounded_send(update(PLAYER_1, 1, now)).unwrap();
+    updates.unbounded_send(update(PLAYER_2, 2, now)).unwrap();
+
+    let released = released(scores, start, 2).await;
+    assert_eq!(released.iter().map(|(s, _)| *s).collect::<Vec<_>>(), [2, 1]);
+    assert!(about(released[0].1, 100), "{:?}", released);
+    assert!(about(released[1].1, 300), "{:?}", released);
+}
+
+#[tokio::test(start_paused = true)]
+async fn scores_wait_for_the_sync_start() {
+    let now = now_ms();
+    let (updates, scores) = delayed(HashMap::from([(
+        PLAYER_1,
+        StreamDelay {
+            delay_ms: 100,
+            sync_start_ms: now + 1_000,
+        },
+    )]));
+    let start = Instant::now();
+    updates.unbounded_send(update(PLAYER_1, 1, now)).unwrap();
+
+    let released = released(scores, start, 1).await;
+    assert!(about(released[0].1, 1_100), "{:?}", released);
+}
+
+#[tokio::test(start_paused = true)]
+async fn scores_come_out_in_release_order() {
+    let (updates, scores) = delayed(HashMap::from([
+        (PLAYER_1, delay(400)),
+        (PLAYER_2, delay(0)),
+    ]));
+    let start = Instant::now();
+    let now = now_ms();
+    for (player, score) in [(PLAYER_1, 1), (PLAYER_2, 2), (PLAYER_1, 3), (PLAYER_2, 4)] {
+        updates.unbounded_send(update(player, score, now)).unwrap();
+    }
+    // the feed ending flushes what's still held back, in order
+    drop(updates);
+
+    let released = released(scores, start, 4).await;
+    assert_eq!(
+        released.iter().map(|(s, _)| *s).collect::<Vec<_>>(),
+        [2, 4, 1, 3]
+    );
+    assert!(about(released[1].1, 0), "{:?}", released);
+    assert!(about(released[3].1, 400), "{:?}", released);
+}
+
+#[tokio::test(start_paused = true)]
+async fn undelayed_scores_pass_straight_through() {
+    let ctx = AppContext::new(config("ws://127.0.0.1:1"));
+    let mut player = user(
+        PLAYER_1,
+        "Alice",
+        tars::proto::models::user::ClientTypes::Player,
+    );
+    player.stream_delay_ms = 5_000;
+    ctx.ta_state.write().await.players = vec![player];
+
+    let mut undelayed = score_updates(ctx.clone(), None, None, false);
+    let mut delayed = score_updates(ctx.clone(), None, None, true);
+    let start = Instant::now();
+    ctx.rts_updates.send(update(PLAYER_1, 1, now_ms()));
+
+    let score = undelayed.next().await.expect("should pass the score on");
+    assert_eq!(score.score.score, 1);
+    assert_eq!(start.elapsed(), Duration::ZERO);
+
+    delayed.next().await.expect("should release the score");
+    assert!(about(start.elapsed(), 5_000), "{:?}", start.elapsed());
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+use tars::{auth::Role, AppContext};
+
+const POOL: &str = r#"mutation { createPool(name: "Finals", maps: [{ label: "NM1", hash: "abc", difficulty: 4 }, { label: "NM2", hash: "def", difficulty: 3 }, { label: "TB", hash: "fed", difficulty: 4 }]) { guid name maps { label hash characteristic } } }"#;
+
+#[tokio::test]
+async fn pools_are_created_listed_and_deleted() {
+    let ctx = AppContext::new(config("ws://127.0.0.1:1"));
+
+    let created = query(&ctx, POOL).await;
+    assert_eq!(created["createPool"]["name"], "Finals");
+    assert_eq!(
+        created["createPool"]["maps"][0],
+        json!({ "label": "NM1", "hash": "ABC", "characteristic": "Standard" })
+    );
+    let pool = created["createPool"]["guid"].as_str().expect("pool id");
+
+    let duplicate = query_as(
+        &ctx,
+        Some(Role::Admin),
+        r#"mutation { createPool(name: "Broken", maps: [{ label: "NM1", hash: "abc", difficulty: 4 }, { label: "NM1", hash: "def", difficulty: 3 }]) { guid } }"#,
+    )
+    .await;
+    assert_eq!(
+        duplicate.errors[0].message,
+        "Map pool contains label NM1 twice."
+    );
+
+    query(
+        &ctx,
+        &format!(
+            r#"mutation {{ setMatchPool(matchId: "{}", poolId: "{}") {{ matchGuid }} }}"#,
+            MATCH, pool
+        ),
+    )
+    .await;
+    let data = query(
+        &ctx,
+        &format!(r#"mutation {{ deletePool(id: "{}") }}"#, pool),
+    )
+    .await;
+    assert_eq!(data["deletePool"], true);
+
+    let data = query(
+        &ctx,
+        &format!(
+            r#"{{ pools {{ name }} pickBan(matchId: "{}") {{ matchGuid }} }}"#,
+            MATCH
+        ),
+    )
+    .await;
+    assert_eq!(data, json!({ "pools": [], "pickBan": null }));
+}
+
+#[tokio::test]
+async fn pick_bans_keep_their_order_through_undo_and_reset() {
+    let config = config("ws://127.0.0.1:1");
+    let ctx = AppContext::new(config.clone());
+    let pool = query(&ctx, POOL).await["createPool"]["guid"]
+        .as_str()
+        .expect("pool id")
+        .to_string();
+
+    let pick_ban = "{ steps { action label } picks { label } bans { label } remaining { label } }";
+    query(
+        &ctx,
+        &format!(
+            r#"mutation {{ setMatchPool(matchId: "{m}", poolId: "{}") {{ matchGuid }} banMap(matchId: "{m}", label: "NM1") {{ matchGuid }} pickMap(matchId: "{m}", label: "TB") {{ matchGuid }} }}"#,
+            pool,
+            m = MATCH
+        ),
+    )
+    .await;
+    let repeated = query_as(
+        &ctx,
+        Some(Role::Producer),
+        &format!(
+            r#"mutation {{ pickMap(matchId: "{}", label: "NM1") {{ matchGuid }} }}"#,
+            MATCH
+        ),
+    )
+    .await;
+    assert_eq!(
+        repeated.errors[0].message,
+        "Map NM1 was already picked or banned."
+    );
+
+    let data = query(
+        &ctx,
+        &format!(r#"{{ pickBan(matchId: "{}") {} }}"#, MATCH, pick_ban),
+    )
+    .await;
+    assert_eq!(
+        data["pickBan"],
+        json!({
+            "steps": [
+                { "action": "BAN", "label": "NM1" },
+                { "action": "PICK", "label": "TB" },
+            ],
+            "picks": [{ "label": "TB" }],
+            "bans": [{ "label": "NM1" }],
+            "remaining": [{ "label": "NM2" }],
+        })
+    );
+
+    // saved as it happens, so a restart picks up where it left off
+    let data = query(
+        &ctx,
+        &format!(
+            r#"mutation {{ undoPickBan(matchId: "{}") {} }}"#,
+            MATCH, pick_ban
+        ),
+    )
+    .await;
+    assert_eq!(
+        data["undoPickBan"]["steps"],
+        json!([{ "action": "BAN", "label": "NM1" }])
+    );
+    let restarted = AppContext::new(config);
+    restarted
+        .pool_state
+        .write()
+        .await
+        .load(&restarted.config)
+        .await
+        .expect("should load the pools");
+    let data = query(
+        &restarted,
+        &format!(r#"{{ pickBan(matchId: "{}") {} }}"#, MATCH, pick_ban),
+    )
+    .await;
+    assert_eq!(
+        data["pickBan"]["steps"],
+        json!([{ "action": "BAN", "label": "NM1" }])
+    );
+
+    let data = query(
+        &restarted,
+        &format!(
+            r#"mutation {{ resetPickBan(matchId: "{}") {} }}"#,
+            MATCH, pick_ban
+        ),
+    )
+    .await;
+    assert_eq!(data["resetPickBan"]["steps"], json!([]));
+    assert_eq!(
+        data["resetPickBan"]["remaining"],
+        json!([{ "label": "NM1" }, { "label": "NM2" }, { "label": "TB" }])
+    );
+}
@@ -0,0 +1,211 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+use tars::proto::{
+    models,
+    packet::{self, event, push},
+};
+
+#[tokio::test]
+async fn connect_response_fills_state() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay(&mock).await;
+
+    let data = query(
+        &ctx,
+        "{ server { name } state { players { name } coordinators { name } matches { guid server } } }",
+    )
+    .await;
+    assert_eq!(
+        data,
+        json!({
+            "server": { "name": "Mock TA" },
+            "state": {
+                "players": [{ "name": "Alice" }, { "name": "Bob" }],
+                "coordinators": [{ "name": "Carol" }],
+                "matches": [{ "guid": MATCH, "server": "Mock TA" }],
+            },
+        })
+    );
+}
+
+#[tokio::test]
+async fn match_by_id_shows_realtime_scores() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay(&mock).await;
+
+    mock.realtime_score(realtime_score(PLAYER_1, 123456, 0.95));
+    eventually(&ctx, |state| state.rts.contains_key(PLAYER_1)).await;
+
+    let data = query(
+        &ctx,
+        &format!(
+            r#"{{ matchById(id: "{}") {{ players {{ name }} coordinators {{ name }} scores {{ ownerGuid score combo }} }} }}"#,
+            MATCH
+        ),
+    )
+    .await;
+    assert_eq!(
+        data,
+        json!({
+            "matchById": {
+                "players": [{ "name": "Alice" }, { "name": "Bob" }],
+                "coordinators": [{ "name": "Carol" }],
+                "scores": [{ "ownerGuid": PLAYER_1, "score": 123456, "combo": 42 }],
+            },
+        })
+    );
+}
+
+#[tokio::test]
+async fn song_finished_keeps_the_final_score() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay(&mock).await;
+
+    mock.realtime_score(realtime_score(PLAYER_2, 98765, 0.9));
+    mock.song_finished(push::SongFinished {
+        player: Some(user(PLAYER_2, "Bob", models::user::ClientTypes::Player)),
+        score: 98765,
+        ..Default::default()
+    });
+    // the test runtime routes packets in order, so once this lands the pushes have too
+    mock.event(event::ChangedObject::UserLeftEvent(event::UserLeftEvent {
+        user: Some(user(PLAYER_1, "Alice", models::user::ClientTypes::Player)),
+    }));
+    eventually(&ctx, |state| state.players.len() == 1).await;
+
+    let data = query(
+        &ctx,
+        &format!(
+            r#"{{ matchById(id: "{}") {{ scores {{ ownerGuid score }} }} }}"#,
+            MATCH
+        ),
+    )
+    .await;
+    assert_eq!(
+        data,
+        json!({ "matchById": { "scores": [{ "ownerGuid": PLAYER_2, "score": 98765 }] } })
+    );
+}
+
+#[tokio::test]
+async fn user_events_update_players() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay(&mock).await;
+
+    let dave = "6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a04";
+    mock.event(event::ChangedObject::UserAddedEvent(
+        event::UserAddedEvent {
+            user: Some(user(dave, "Dave", models::user::ClientTypes::Player)),
+        },
+    ));
+    mock.event(event::ChangedObject::UserLeftEvent(event::UserLeftEvent {
+        user: Some(user(PLAYER_2, "Bob", models::user::ClientTypes::Player)),
+    }));
+    eventually(&ctx, |state| {
+        state.players.iter().any(|p| p.guid == dave) && state.players.len() == 2
+    })
+    .await;
+
+    let data = query(&ctx, "{ state { players { guid name } } }").await;
+    assert_eq!(
+        data,
+        json!({
+            "state": {
+                "players": [
+                    { "guid": PLAYER_1, "name": "Alice" },
+                    { "guid": dave, "name": "Dave" },
+                ],
+            },
+        })
+    );
+}
+
+#[tokio::test]
+async fn created_matches_include_the_relay() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay(&mock).await;
+    let relay_guid = ctx.ta_state.read().await.server_users[0].guid.clone();
+
+    let created = "6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a11";
+    mock.event(event::ChangedObject::MatchCreatedEvent(
+        event::MatchCreatedEvent {
+            r#match: Some(models::Match {
+                guid: created.to_string(),
+                associated_users: vec![PLAYER_1.to_string(), COORDINATOR.to_string()],
+                ..Default::default()
+            }),
+        },
+    ));
+    eventually(&ctx, |state| {
+        state.matches.iter().any(|m| m.guid == created)
+    })
+    .await;
+
+    // the relay tells TA about the overlay over a separate connection
+    let waiting = async {
+        loop {
+            let updated = mock
+                .received()
+                .await
+                .into_iter()
+                .find_map(|p| match p.packet {
+                    Some(packet::packet::Packet::Event(packet::Event {
+                        changed_object: Some(event::ChangedObject::MatchUpdatedEvent(e)),
+                    })) => e.r#match,
+                    _ => None,
+                });
+            if let Some(updated) = updated {
+                return updated;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    };
+    let updated = tokio::time::timeout(std::time::Duration::from_secs(5), waiting)
+        .await
+        .expect("the relay should have sent a MatchUpdatedEvent");
+    assert_eq!(updated.guid, created);
+    assert!(updated.associated_users.contains(&relay_guid));
+}
+
+// several worker threads, so packets processed concurrently could overtake each other
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn packets_are_applied_in_the_order_they_arrive() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay(&mock).await;
+
+    let r#match = |i: usize| models::Match {
+        guid: format!("6b7a4c1e-0d3f-4f1a-9a55-{:012}", i),
+        ..Default::default()
+    };
+    for i in 0..50 {
+        mock.event(event::ChangedObject::MatchCreatedEvent(
+            event::MatchCreatedEvent {
+                r#match: Some(r#match(i)),
+            },
+        ));
+        mock.event(event::ChangedObject::MatchDeletedEvent(
+            event::MatchDeletedEvent {
+                r#match: Some(r#match(i)),
+            },
+        ));
+    }
+    let last = r#match(50).guid;
+    mock.event(event::ChangedObject::MatchCreatedEvent(
+        event::MatchCreatedEvent {
+            r#match: Some(r#match(50)),
+        },
+    ));
+
+    eventually(&ctx, |state| state.matches.iter().any(|m| m.guid == last)).await;
+    let guids = ctx
+        .ta_state
+        .read()
+        .await
+        .matches
+        .iter()
+        .map(|m| m.guid.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(guids, [MATCH.to_string(), last]);
+}
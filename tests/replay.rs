@@ -0,0 +1,59 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+use tars::{capture, config::ReplaySpeed, proto::packet, replay, AppContext};
+
+#[tokio::test]
+async fn captured_session_replays_into_the_same_state() {
+    let mock = MockTa::start(tournament()).await;
+    let mut live_config = config(&mock.uri);
+    let path = live_config.data_path("session.capture");
+    std::fs::create_dir_all(&live_config.data_dir).expect("should create the data directory");
+    live_config.capture = Some(path.clone());
+    let live = relay_with(live_config).await;
+
+    mock.realtime_score(realtime_score(PLAYER_1, 1000, 0.8));
+    mock.realtime_score(realtime_score(PLAYER_1, 2000, 0.85));
+    eventually(&live, |state| {
+        state.rts.get(PLAYER_1).is_some_and(|s| s.score == 2000)
+    })
+    .await;
+
+    let records = capture::read(&path).await.expect("should read the capture");
+    assert_eq!(records.len(), 3);
+    assert!(matches!(
+        records[0].packet.as_ref().and_then(|p| p.packet.as_ref()),
+        Some(packet::packet::Packet::Response(_))
+    ));
+    assert!(records
+        .windows(2)
+        .all(|r| r[0].received_at_ms <= r[1].received_at_ms));
+
+    let mut replay_config = config("");
+    replay_config.replay = Some(path);
+    replay_config.replay_speed = ReplaySpeed::Stepped;
+    let replayed = AppContext::new(replay_config);
+    tokio::spawn(replay::run(replayed.clone()));
+
+    // nothing is replayed until it's stepped
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert_eq!(
+        query(&replayed, "{ replay { position total stepped } }").await,
+        json!({ "replay": { "position": 0, "total": 3, "stepped": true } })
+    );
+
+    query(&replayed, "mutation { stepReplay(packets: 2) }").await;
+    eventually(&replayed, |state| {
+        state.rts.get(PLAYER_1).is_some_and(|s| s.score == 1000)
+    })
+    .await;
+    query(&replayed, "mutation { stepReplay }").await;
+    eventually(&replayed, |state| {
+        state.rts.get(PLAYER_1).is_some_and(|s| s.score == 2000)
+    })
+    .await;
+
+    let state = "{ server { name } state { players { name } matches { guid scores { score } } } }";
+    assert_eq!(query(&replayed, state).await, query(&live, state).await);
+}
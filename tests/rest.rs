@@ -0,0 +1,45 @@
+mod common;
+
+use common::*;
+
+fn etag(head: &str) -> &str {
+    head.lines()
+        .find_map(|l| l.strip_prefix("etag: "))
+        .expect("should have an ETag")
+}
+
+#[tokio::test]
+async fn matches_by_id_with_etags() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = relay_with_http(&mock).await;
+    let path = format!("/api/v1/matches/{}", MATCH);
+
+    let (head, body) = http_get(ctx.config.port, &path, "").await;
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let body: serde_json::Value = serde_json::from_str(&body).expect("should be JSON");
+    assert_eq!(body["guid"], MATCH);
+    assert_eq!(body["players"][0]["name"], "Alice");
+
+    let tag = etag(&head);
+    let (head, body) = http_get(
+        ctx.config.port,
+        &path,
+        &format!("If-None-Match: {}\r\n", tag),
+    )
+    .await;
+    assert!(head.starts_with("HTTP/1.1 304"), "{}", head);
+    assert_eq!(etag(&head), tag);
+    assert!(body.is_empty());
+
+    let (head, _) = http_get(
+        ctx.config.port,
+        "/api/v1/players/00000000-0000-0000-0000-000000000000",
+        "",
+    )
+    .await;
+    assert!(head.starts_with("HTTP/1.1 404"), "{}", head);
+
+    let (head, body) = http_get(ctx.config.port, "/api/v1/state?server=nowhere", "").await;
+    assert!(head.starts_with("HTTP/1.1 404"), "{}", head);
+    assert!(body.contains("No server nowhere."), "{}", body);
+}
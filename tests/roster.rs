@@ -0,0 +1,69 @@
+mod common;
+
+use std::time::{Duration, SystemTime};
+
+use common::*;
+use serde_json::json;
+use tars::{proto::models, roster::parse_roster, AppContext};
+
+#[test]
+fn csv_rosters_are_keyed_by_their_user_id_column() {
+    let profiles = parse_roster(
+        "roster.csv",
+        b"user_id,display_name,country\n765611901,Alice A.,NL\n",
+    )
+    .expect("should parse the roster");
+    assert_eq!(
+        profiles["765611901"].display_name.as_deref(),
+        Some("Alice A.")
+    );
+    assert_eq!(profiles["765611901"].country.as_deref(), Some("NL"));
+}
+
+#[tokio::test]
+async fn profiles_follow_the_roster_file() {
+    let ctx = AppContext::new(config("ws://127.0.0.1:1"));
+    ctx.ta_state.write().await.players = vec![
+        user(PLAYER_1, "Alice", models::user::ClientTypes::Player),
+        user(PLAYER_2, "Bob", models::user::ClientTypes::Player),
+    ];
+    let path = ctx.config.data_dir.join("roster.json");
+    std::fs::create_dir_all(&ctx.config.data_dir).expect("should create the data directory");
+    let path = path.to_str().expect("should be a UTF-8 path").to_string();
+    let players = "{ state { players { displayName profile { country } } } }";
+
+    assert!(!ctx.roster.write().await.reload(&path).await.unwrap());
+    std::fs::write(
+        &path,
+        r#"{ "765611901": { "display_name": "Alice A.", "country": "NL" } }"#,
+    )
+    .expect("should write the roster");
+    assert!(ctx.roster.write().await.reload(&path).await.unwrap());
+    assert!(!ctx.roster.write().await.reload(&path).await.unwrap());
+    assert_eq!(
+        query(&ctx, players).await["state"]["players"],
+        json!([
+            { "displayName": "Alice A.", "profile": { "country": "NL" } },
+            { "displayName": "Bob", "profile": null },
+        ])
+    );
+
+    // set the time explicitly, as writes within the same tick keep it unchanged
+    let file = std::fs::File::options()
+        .write(true)
+        .truncate(true)
+        .open(&path)
+        .expect("should open the roster");
+    std::io::Write::write_all(&mut &file, br#"{ "765611902": { "country": "DE" } }"#)
+        .expect("should rewrite the roster");
+    file.set_modified(SystemTime::now() + Duration::from_secs(5))
+        .expect("should set the modified time");
+    assert!(ctx.roster.write().await.reload(&path).await.unwrap());
+    assert_eq!(
+        query(&ctx, players).await["state"]["players"],
+        json!([
+            { "displayName": "Alice", "profile": null },
+            { "displayName": "Bob", "profile": { "country": "DE" } },
+        ])
+    );
+}
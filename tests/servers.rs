@@ -0,0 +1,216 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use serde_json::json;
+use tars::{
+    config::ServerConfig,
+    proto::{
+        models,
+        packet::{self, event},
+    },
+    Ctx,
+};
+
+const OTHER_MATCH: &str = "6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a20";
+
+/// A second server, with its own name and match.
+fn other_tournament() -> models::State {
+    let mut state = tournament();
+    if let Some(settings) = &mut state.server_settings {
+        settings.server_name = "Other TA".to_string();
+    }
+    state.matches[0].guid = OTHER_MATCH.to_string();
+    state
+}
+
+fn host(name: &str, mock: &MockTa) -> models::CoreServer {
+    let (address, port) = mock
+        .uri
+        .trim_start_matches("ws://")
+        .rsplit_once(':')
+        .expect("the mock should have a port");
+    models::CoreServer {
+        name: name.to_string(),
+        address: address.to_string(),
+        websocket_port: port.parse().expect("should be a port"),
+        ..Default::default()
+    }
+}
+
+/// Waits up to 5 seconds for the relay to list `count` connected servers.
+async fn connected_servers(ctx: &Ctx, count: usize) -> serde_json::Value {
+    let waiting = async {
+        loop {
+            let data = query(ctx, "{ servers { name primary connected } }").await;
+            let servers = data["servers"].as_array().expect("servers");
+            if servers.len() == count && servers.iter().all(|s| s["connected"] == true) {
+                return data["servers"].clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), waiting)
+        .await
+        .expect("the relay should connect to the servers")
+}
+
+#[tokio::test]
+async fn configured_servers_are_relayed_alongside_the_primary() {
+    let primary = MockTa::start(tournament()).await;
+    let other = MockTa::start(other_tournament()).await;
+    let mut config = config(&primary.uri);
+    config.servers = vec![ServerConfig {
+        uri: other.uri.clone(),
+        password: String::new(),
+    }];
+    let ctx = relay_with(config).await;
+
+    assert_eq!(
+        connected_servers(&ctx, 2).await,
+        json!([
+            { "name": "Mock TA", "primary": true, "connected": true },
+            { "name": "Other TA", "primary": false, "connected": true },
+        ])
+    );
+    let data = query(
+        &ctx,
+        r#"{ allMatches { guid server } state(server: "Other TA") { matches { guid } } primary: state { matches { guid } } }"#,
+    )
+    .await;
+    assert_eq!(
+        data,
+        json!({
+            "allMatches": [
+                { "guid": MATCH, "server": "Mock TA" },
+                { "guid": OTHER_MATCH, "server": "Other TA" },
+            ],
+            "state": { "matches": [{ "guid": OTHER_MATCH }] },
+            "primary": { "matches": [{ "guid": MATCH }] },
+        })
+    );
+    let missing = query_as(
+        &ctx,
+        Some(tars::auth::Role::Admin),
+        r#"{ state(server: "Nowhere") { matches { guid } } }"#,
+    )
+    .await;
+    assert_eq!(missing.errors[0].message, "No server Nowhere.");
+}
+
+#[tokio::test]
+async fn known_hosts_are_followed() {
+    let other = MockTa::start(other_tournament()).await;
+    let mut state = tournament();
+    // TA lists itself among its known hosts
+    let itself = models::CoreServer {
+        name: "Mock TA".to_string(),
+        address: "localhost".to_string(),
+        ..Default::default()
+    };
+    state.known_hosts = vec![itself, host("Other TA", &other)];
+    let primary = MockTa::start(state).await;
+    let mut config = config(&primary.uri);
+    config.discover_servers = true;
+    let ctx = relay_with(config).await;
+
+    connected_servers(&ctx, 2).await;
+    assert_eq!(ctx.server_states.read().await.len(), 1);
+
+    // realtime scores and other packets don't connect again
+    primary.realtime_score(realtime_score(PLAYER_1, 1, 0.9));
+    primary.event(event::ChangedObject::HostDeletedEvent(
+        event::HostDeletedEvent {
+            server: Some(host("Other TA", &other)),
+        },
+    ));
+    connected_servers(&ctx, 1).await;
+
+    primary.event(event::ChangedObject::HostAddedEvent(
+        event::HostAddedEvent {
+            server: Some(host("Other TA", &other)),
+        },
+    ));
+    connected_servers(&ctx, 2).await;
+    let connects = other
+        .received()
+        .await
+        .into_iter()
+        .filter(|p| {
+            matches!(
+                p.packet,
+                Some(packet::packet::Packet::Request(packet::Request {
+                    r#type: Some(packet::request::Type::Connect(_)),
+                }))
+            )
+        })
+        .count();
+    assert_eq!(connects, 2);
+}
+
+#[tokio::test]
+async fn other_servers_keep_one_tx_connection() {
+    let primary = MockTa::start(tournament()).await;
+    let other = MockTa::start(other_tournament()).await;
+    let mut config = config(&primary.uri);
+    config.servers = vec![ServerConfig {
+        uri: other.uri.clone(),
+        password: String::new(),
+    }];
+    let ctx = relay_with(config).await;
+    connected_servers(&ctx, 2).await;
+
+    // the relay's RX user joins each new match, which it tells TA as its TX user
+    for guid in [
+        "6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a21",
+        "6b7a4c1e-0d3f-4f1a-9a55-1f3c2b9e7a22",
+    ] {
+        other.event(event::ChangedObject::MatchCreatedEvent(
+            event::MatchCreatedEvent {
+                r#match: Some(models::Match {
+                    guid: guid.to_string(),
+                    associated_users: vec![PLAYER_1.to_string()],
+                    ..Default::default()
+                }),
+            },
+        ));
+    }
+
+    let sent = async {
+        loop {
+            let received = other.received().await;
+            let updates = received
+                .iter()
+                .filter(|p| {
+                    matches!(
+                        &p.packet,
+                        Some(packet::packet::Packet::Event(packet::Event {
+                            changed_object: Some(event::ChangedObject::MatchUpdatedEvent(_)),
+                        }))
+                    )
+                })
+                .count();
+            if updates == 2 {
+                return received;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    let received = tokio::time::timeout(Duration::from_secs(5), sent)
+        .await
+        .expect("the relay should update both matches");
+    let connects = received
+        .iter()
+        .filter_map(|p| match &p.packet {
+            Some(packet::packet::Packet::Request(packet::Request {
+                r#type: Some(packet::request::Type::Connect(connect)),
+            })) => connect.user.as_ref().map(|u| u.name.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        connects,
+        [ctx.config.rx_name.clone(), ctx.config.tx_name.clone()]
+    );
+}
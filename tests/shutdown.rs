@@ -0,0 +1,47 @@
+mod common;
+
+use std::time::Duration;
+
+use common::*;
+use futures_util::StreamExt;
+use serde_json::json;
+use tars::{auth::Keys, relay, AppContext, TAConnection};
+use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+#[tokio::test]
+async fn shutdown_completes_subscriptions_and_stops_the_relay() {
+    let mock = MockTa::start(tournament()).await;
+    let ctx = AppContext::new(http_config(&mock.uri));
+    let con = TAConnection::connect(&ctx.config.ta_uri, &ctx.config.rx_name, "")
+        .await
+        .expect("should connect to the mock server");
+    let relay = tokio::spawn(relay::run(ctx.clone(), Keys::disabled(), Some(con)));
+    eventually(&ctx, |state| state.server_settings.is_some()).await;
+    listening(&ctx).await;
+
+    let mut ws = graphql_ws(ctx.config.port, "").await;
+    ws_send(
+        &mut ws,
+        json!({ "id": "1", "type": "subscribe", "payload": { "query": "subscription { bracket { name } }" } }),
+    )
+    .await;
+    assert_eq!(
+        ws_next(&mut ws).await["payload"]["data"],
+        json!({ "bracket": null })
+    );
+
+    ctx.shutdown.trigger();
+    assert_eq!(
+        ws_next(&mut ws).await,
+        json!({ "id": "1", "type": "complete" })
+    );
+    match tokio::time::timeout(Duration::from_secs(5), ws.next()).await {
+        Ok(Some(Ok(Message::Close(Some(frame))))) => assert_eq!(frame.code, CloseCode::Away),
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+
+    tokio::time::timeout(Duration::from_secs(15), relay)
+        .await
+        .expect("the relay should stop")
+        .expect("the relay should not panic");
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::{collections::HashSet, time::Duration};
+
+use common::*;
+use futures_util::StreamExt;
+use tars::{
+    gql,
+    proto::packet::{self, push},
+    simulator, AppContext,
+};
+
+#[tokio::test]
+async fn simulated_matches_play_through_a_song() {
+    let mut config = config("");
+    config.simulator.enabled = true;
+    config.simulator.players = 5;
+    config.simulator.players_per_match = 2;
+    config.simulator.teams = 2;
+    config.simulator.song_seconds = 1;
+    config.simulator.seed = Some(7);
+    let ctx = AppContext::new(config);
+    tokio::spawn(simulator::run(ctx.clone()));
+
+    eventually(&ctx, |state| {
+        state.players.len() == 5 && state.rts.len() == 5
+    })
+    .await;
+
+    let data = query(
+        &ctx,
+        "{ server { name settings { enableTeams teams { name } } } state { players { name team { name } } coordinators { name } matches { players { name } currentMap { name } } } }",
+    )
+    .await;
+    assert_eq!(data["server"]["name"], "TARS Simulator");
+    assert_eq!(data["server"]["settings"]["enableTeams"], true);
+    assert_eq!(
+        data["server"]["settings"]["teams"].as_array().map(Vec::len),
+        Some(2)
+    );
+    assert_eq!(
+        data["state"]["coordinators"].as_array().map(Vec::len),
+        Some(3)
+    );
+    let matches = data["state"]["matches"].as_array().expect("matches");
+    assert_eq!(
+        matches
+            .iter()
+            .map(|m| m["players"].as_array().map(Vec::len).unwrap_or_default())
+            .collect::<Vec<_>>(),
+        vec![2, 2, 1]
+    );
+    assert!(matches.iter().all(|m| m["currentMap"]["name"].is_string()));
+
+    let state = ctx.ta_state.read().await;
+    for score in state.rts.values() {
+        assert!(score.score <= score.max_score);
+        assert!((0.0..=1.0).contains(&score.accuracy));
+        assert!(score.max_combo >= score.combo);
+    }
+}
+
+#[tokio::test]
+async fn seeded_runs_finish_the_same_song_for_every_player() {
+    let finished = |seed| async move {
+        let mut config = config("");
+        config.simulator.enabled = true;
+        config.simulator.players = 3;
+        config.simulator.players_per_match = 3;
+        config.simulator.song_seconds = 1;
+        config.simulator.seed = Some(seed);
+        let ctx = AppContext::new(config);
+        let packets = gql::sink_stream(&ctx.ta_packets).filter_map(|p| async move {
+            match p.packet {
+                Some(packet::packet::Packet::Push(packet::Push {
+                    data: Some(push::Data::SongFinished(finished)),
+                })) => Some(finished),
+                _ => None,
+            }
+        });
+        tokio::spawn(simulator::run(ctx.clone()));
+
+        let finished =
+            tokio::time::timeout(Duration::from_secs(10), packets.take(3).collect::<Vec<_>>())
+                .await
+                .expect("every player should finish the song");
+        ctx.shutdown.trigger();
+        finished
+    };
+
+    let first = finished(11).await;
+    let players = first
+        .iter()
+        .map(|f| f.player.as_ref().map(|p| p.name.clone()))
+        .collect::<HashSet<_>>();
+    assert_eq!(players.len(), 3);
+    let levels = first
+        .iter()
+        .map(|f| f.beatmap.as_ref().map(|b| b.level_id.clone()))
+        .collect::<HashSet<_>>();
+    assert_eq!(levels.len(), 1);
+
+    // the same seed plays the same song to the same scores
+    let scores = |finished: &[push::SongFinished]| {
+        finished
+            .iter()
+            .map(|f| (f.beatmap.clone().map(|b| b.level_id), f.score))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(scores(&first), scores(&finished(11).await));
+}
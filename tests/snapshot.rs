@@ -0,0 +1,81 @@
+mod common;
+
+use common::*;
+use serde_json::json;
+use tars::{ingest, snapshot, AppContext, TAConnection};
+
+#[tokio::test]
+async fn restart_restores_the_snapshot_then_reconciles() {
+    let mock = MockTa::start(tournament()).await;
+    let config = config(&mock.uri);
+    let before = relay_with(config.clone()).await;
+
+    mock.realtime_score(realtime_score(PLAYER_1, 1111, 0.9));
+    mock.realtime_score(realtime_score(PLAYER_2, 2222, 0.9));
+    eventually(&before, |state| state.rts.len() == 2).await;
+    let pool = query(
+        &before,
+        r#"mutation { createPool(name: "Finals", maps: [{ label: "NM1", hash: "abc", difficulty: 4 }, { label: "NM2", hash: "def", difficulty: 3 }]) { guid } }"#,
+    )
+    .await;
+    let pick_ban = format!(
+        r#"mutation {{ setMatchPool(matchId: "{}", poolId: "{}") {{ matchGuid }} pickMap(matchId: "{}", label: "NM2") {{ matchGuid }} }}"#,
+        MATCH,
+        pool["createPool"]["guid"].as_str().expect("pool id"),
+        MATCH,
+    );
+    query(&before, &pick_ban).await;
+    snapshot::save(&before)
+        .await
+        .expect("should save the snapshot");
+
+    // Bob left the TA server while the relay was down
+    let mut changed = tournament();
+    changed.users.retain(|u| u.guid != PLAYER_2);
+    let mock = MockTa::start(changed).await;
+    let mut config = config;
+    config.ta_uri = mock.uri.clone();
+    let after = AppContext::new(config);
+    after
+        .pool_state
+        .write()
+        .await
+        .load(&after.config)
+        .await
+        .expect("should load the pools");
+    snapshot::restore(&after)
+        .await
+        .expect("should restore the snapshot");
+
+    let scores = format!(
+        r#"{{ matchById(id: "{}") {{ players {{ name }} scores {{ score }} }} pickBan(matchId: "{}") {{ picks {{ label }} }} }}"#,
+        MATCH, MATCH
+    );
+    assert_eq!(
+        query(&after, &scores).await,
+        json!({
+            "matchById": {
+                "players": [{ "name": "Alice" }, { "name": "Bob" }],
+                "scores": [{ "score": 1111 }, { "score": 2222 }],
+            },
+            "pickBan": { "picks": [{ "label": "NM2" }] },
+        })
+    );
+
+    let con = TAConnection::connect(&after.config.ta_uri, &after.config.rx_name, "")
+        .await
+        .expect("should connect to the mock server");
+    tokio::spawn(ingest::run(after.clone(), Some(con)));
+    eventually(&after, |state| state.players.len() == 1).await;
+
+    assert_eq!(
+        query(&after, &scores).await,
+        json!({
+            "matchById": {
+                "players": [{ "name": "Alice" }],
+                "scores": [{ "score": 1111 }],
+            },
+            "pickBan": { "picks": [{ "label": "NM2" }] },
+        })
+    );
+}